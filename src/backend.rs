@@ -0,0 +1,711 @@
+use crate::{Commit, Error};
+use std::cell::RefCell;
+use std::collections::{BinaryHeap, HashMap};
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Queries about the history the graph is built from
+///
+/// `Repository` uses `GixBackend` unless told otherwise with
+/// `Repository::with_backend()`, which also lets tests serve a canned
+/// history.
+pub trait GitBackend: std::fmt::Debug {
+    /// Commit a revision points to, peeling annotated tags, or `None` if it
+    /// doesn't exist
+    fn resolve_ref(&self, rev: &str) -> Result<Option<Commit>>;
+
+    /// Best common ancestors of two commits, empty when their histories are
+    /// unrelated
+    fn merge_base(&self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>>;
+
+    /// Merge bases of many pairs, in the same order, one pair after the
+    /// other unless the backend knows better
+    fn merge_bases(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<Vec<Commit>>> {
+        pairs
+            .iter()
+            .map(|(lhs, rhs)| self.merge_base(lhs, rhs))
+            .collect()
+    }
+
+    /// Numbers of commits only reachable from `lhs`, and only from `rhs`,
+    /// like `git rev-list --left-right --count lhs...rhs`
+    fn ahead_behind(&self, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)>;
+
+    /// Number of commits reachable from `to` but not from `from`, like
+    /// `git rev-list --count from..to`
+    fn count(&self, from: &Commit, to: &Commit) -> Result<usize> {
+        Ok(self.ahead_behind(from, to)?.1)
+    }
+
+    /// Counts of many pairs, in the same order, one pair after the other
+    /// unless the backend knows better
+    fn counts(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<usize>> {
+        pairs
+            .iter()
+            .map(|(from, to)| self.count(from, to))
+            .collect()
+    }
+
+    /// Date, author and subject of a commit, or `None` if it doesn't exist
+    fn commit_info(&self, commit: &Commit) -> Result<Option<CommitInfo>>;
+}
+
+/// What the graph shows of a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// Committer date
+    pub time: gix::date::Time,
+    pub author_name: String,
+    pub author_email: String,
+    /// First line of the message
+    pub subject: String,
+}
+
+/// Backend walking the history in-process with gix, and falling back to git
+/// for the revisions gix can't parse
+#[derive(Debug)]
+pub struct GixBackend {
+    git: gix::Repository,
+    fallback: GitCommandBackend,
+}
+
+impl GixBackend {
+    /// Backend for a repository opened with gix, running `git_binary` for
+    /// what gix doesn't support
+    pub fn new(git: gix::Repository, git_binary: impl Into<PathBuf>) -> Self {
+        let directory = git.work_dir().unwrap_or(git.git_dir()).to_path_buf();
+        GixBackend {
+            git,
+            fallback: GitCommandBackend::new(git_binary, directory),
+        }
+    }
+}
+
+impl GitBackend for GixBackend {
+    fn resolve_ref(&self, rev: &str) -> Result<Option<Commit>> {
+        let spec = commit_spec(rev);
+        match self.git.rev_parse_single(spec.as_str()) {
+            Ok(id) => Ok(Some(Commit::from(id.to_string()))),
+            // The revision may not exist, or use a syntax gix doesn't
+            // support, let git decide
+            Err(e) => {
+                log::debug!("gix failed to resolve {:?}: {}", rev, e);
+                self.fallback.resolve_ref(rev)
+            }
+        }
+    }
+
+    fn merge_base(&self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>> {
+        let cache = self.git.commit_graph_if_enabled().map_err(Error::git)?;
+        let mut graph = self.git.revision_graph(cache.as_ref());
+        Ok(self
+            .git
+            .merge_bases_many_with_graph(lhs.id()?, &[rhs.id()?], &mut graph)
+            .map_err(Error::git)?
+            .into_iter()
+            .map(|id| Commit::from(id.to_string()))
+            .collect())
+    }
+
+    /// The pairs are split between a worker per available core, each sharing
+    /// its own revision graph across its pairs so commits are only loaded
+    /// once per worker.
+    fn merge_bases(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<Vec<Commit>>> {
+        let shared = self.git.clone().into_sync();
+        in_parallel(pairs, |chunk| {
+            let git = worker_repository(&shared);
+            let cache = git.commit_graph_if_enabled().map_err(Error::git)?;
+            let mut graph = git.revision_graph(cache.as_ref());
+            chunk
+                .iter()
+                .map(|(lhs, rhs)| {
+                    Ok(git
+                        .merge_bases_many_with_graph(lhs.id()?, &[rhs.id()?], &mut graph)
+                        .map_err(Error::git)?
+                        .into_iter()
+                        .map(|id| Commit::from(id.to_string()))
+                        .collect())
+                })
+                .collect()
+        })
+    }
+
+    fn ahead_behind(&self, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)> {
+        ahead_behind(&self.git, lhs, rhs)
+    }
+
+    /// The pairs are split between a worker per available core, like the
+    /// merge bases.
+    fn counts(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<usize>> {
+        let shared = self.git.clone().into_sync();
+        in_parallel(pairs, |chunk| {
+            let git = worker_repository(&shared);
+            chunk
+                .iter()
+                .map(|(from, to)| Ok(ahead_behind(&git, from, to)?.1))
+                .collect()
+        })
+    }
+
+    fn commit_info(&self, commit: &Commit) -> Result<Option<CommitInfo>> {
+        let Some(object) = self.git.try_find_object(commit.id()?).map_err(Error::git)? else {
+            return Ok(None);
+        };
+        let object = object.try_into_commit().map_err(Error::git)?;
+        let author = object.author().map_err(Error::git)?;
+        Ok(Some(CommitInfo {
+            time: object.time().map_err(Error::git)?,
+            author_name: author.name.to_string(),
+            author_email: author.email.to_string(),
+            subject: object.message().map_err(Error::git)?.summary().to_string(),
+        }))
+    }
+}
+
+/// Repository of a worker thread, with the object cache of the main one
+fn worker_repository(shared: &gix::ThreadSafeRepository) -> gix::Repository {
+    let mut git = shared.to_thread_local();
+    git.object_cache_size_if_unset(4 * 1024 * 1024);
+    git
+}
+
+/// Numbers of commits only reachable from `lhs`, and only from `rhs`
+///
+/// Both histories are walked together from the most recent commit down,
+/// marking each commit with the sides it is reachable from, until only
+/// commits reachable from both sides and older than any commit reachable
+/// from a single side are left to walk. This trusts parents to be no more
+/// recent than their children, like git does.
+fn ahead_behind(git: &gix::Repository, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)> {
+    const LEFT: u8 = 1;
+    const RIGHT: u8 = 2;
+    const BOTH: u8 = LEFT | RIGHT;
+
+    let time = |id: gix::ObjectId| -> Result<i64> {
+        let commit = git.find_commit(id).map_err(Error::git)?;
+        Ok(commit.time().map_err(Error::git)?.seconds)
+    };
+
+    let mut sides = HashMap::<gix::ObjectId, u8>::new();
+    *sides.entry(lhs.id()?).or_default() |= LEFT;
+    *sides.entry(rhs.id()?).or_default() |= RIGHT;
+    // Most recent first
+    let mut queue = BinaryHeap::new();
+    for id in sides.keys() {
+        queue.push((time(*id)?, *id));
+    }
+
+    // Date of the oldest commit found reachable from a single side, which
+    // the common commits still to walk could be descendants of
+    let mut oldest = i64::MAX;
+    while let Some(&(newest, _)) = queue.peek() {
+        if newest < oldest && queue.iter().all(|(_, id)| sides[id] == BOTH) {
+            break;
+        }
+        let (date, id) = queue.pop().expect("peeked");
+        let side = sides[&id];
+        if side != BOTH {
+            oldest = oldest.min(date);
+        }
+        let commit = git.find_commit(id).map_err(Error::git)?;
+        for parent in commit.parent_ids() {
+            let parent = parent.detach();
+            let parent_sides = sides.entry(parent).or_default();
+            if *parent_sides | side != *parent_sides {
+                *parent_sides |= side;
+                queue.push((time(parent)?, parent));
+            }
+        }
+    }
+
+    let count = |side| sides.values().filter(|sides| **sides == side).count();
+    Ok((count(LEFT), count(RIGHT)))
+}
+
+/// Backend running a git executable for every query
+#[derive(Debug, Clone)]
+pub struct GitCommandBackend {
+    git_binary: PathBuf,
+    directory: PathBuf,
+}
+
+impl GitCommandBackend {
+    /// Backend running `git_binary` in `directory`
+    pub fn new(git_binary: impl Into<PathBuf>, directory: impl Into<PathBuf>) -> Self {
+        GitCommandBackend {
+            git_binary: git_binary.into(),
+            directory: directory.into(),
+        }
+    }
+}
+
+impl GitBackend for GitCommandBackend {
+    fn resolve_ref(&self, rev: &str) -> Result<Option<Commit>> {
+        let spec = commit_spec(rev);
+        match run_git(
+            &self.git_binary,
+            &self.directory,
+            &["rev-parse", "--verify", "--quiet", &spec],
+        ) {
+            Ok(output) => {
+                let id = String::from_utf8_lossy(&output.stdout);
+                Ok(Some(Commit::parse(id.trim())?))
+            }
+            Err(Error::GitCommand { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn merge_base(&self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>> {
+        let args = ["merge-base", "--all", lhs.as_str(), rhs.as_str()];
+        match run_git(&self.git_binary, &self.directory, &args) {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(Commit::parse)
+                .collect(),
+            // Exits with 1 and no error when the histories are unrelated
+            Err(Error::GitCommand {
+                status: Some(1),
+                stderr,
+                ..
+            }) if stderr.is_empty() => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn ahead_behind(&self, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)> {
+        let range = format!("{}...{}", lhs.as_str(), rhs.as_str());
+        let args = ["rev-list", "--left-right", "--count", &range];
+        let output = run_git(&self.git_binary, &self.directory, &args)?;
+        let output = String::from_utf8_lossy(&output.stdout);
+        output
+            .trim()
+            .split_once('\t')
+            .and_then(|(ahead, behind)| Some((ahead.parse().ok()?, behind.parse().ok()?)))
+            .ok_or_else(|| Error::UnexpectedOutput {
+                command: format!("git {}", args.join(" ")),
+                output: output.to_string(),
+            })
+    }
+
+    fn count(&self, from: &Commit, to: &Commit) -> Result<usize> {
+        let range = format!("{}..{}", from.as_str(), to.as_str());
+        let args = ["rev-list", "--count", &range];
+        let output = run_git(&self.git_binary, &self.directory, &args)?;
+        let output = String::from_utf8_lossy(&output.stdout);
+        output.trim().parse().map_err(|_| Error::UnexpectedOutput {
+            command: format!("git {}", args.join(" ")),
+            output: output.to_string(),
+        })
+    }
+
+    /// The pairs are split between a worker per available core, each running
+    /// git for its pairs.
+    fn counts(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<usize>> {
+        in_parallel(pairs, |chunk| {
+            chunk
+                .iter()
+                .map(|(from, to)| self.count(from, to))
+                .collect()
+        })
+    }
+
+    fn commit_info(&self, commit: &Commit) -> Result<Option<CommitInfo>> {
+        let args = [
+            "log",
+            "-1",
+            "--no-show-signature",
+            "--date=raw",
+            "--format=%cd%n%an%n%ae%n%s",
+            commit.as_str(),
+        ];
+        let output = match run_git(&self.git_binary, &self.directory, &args) {
+            Ok(output) => output,
+            Err(Error::GitCommand { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let output = String::from_utf8_lossy(&output.stdout);
+        let mut lines = output.lines();
+        let time = lines
+            .next()
+            .and_then(|date| gix::date::parse(date, None).ok());
+        match (time, lines.next(), lines.next()) {
+            (Some(time), Some(name), Some(email)) => Ok(Some(CommitInfo {
+                time,
+                author_name: name.to_string(),
+                author_email: email.to_string(),
+                subject: lines.next().unwrap_or_default().to_string(),
+            })),
+            _ => Err(Error::UnexpectedOutput {
+                command: format!("git {}", args.join(" ")),
+                output: output.to_string(),
+            }),
+        }
+    }
+}
+
+/// Backend resolving the revisions with a single `git cat-file --batch-check`
+/// process started on first use, rather than a git per revision
+///
+/// Only the revisions go through that process: like `GitCommandBackend`, it
+/// runs git once for every merge base, count and commit it is asked about.
+#[derive(Debug)]
+pub struct GitCatFileBackend {
+    command: GitCommandBackend,
+    batch: RefCell<Option<Batch>>,
+}
+
+impl GitCatFileBackend {
+    /// Backend running `git_binary` in `directory`
+    pub fn new(git_binary: impl Into<PathBuf>, directory: impl Into<PathBuf>) -> Self {
+        GitCatFileBackend {
+            command: GitCommandBackend::new(git_binary, directory),
+            batch: RefCell::new(None),
+        }
+    }
+
+    fn spawn(&self) -> Result<Batch> {
+        let GitCommandBackend {
+            git_binary,
+            directory,
+        } = &self.command;
+        let mut child = match std::process::Command::new(git_binary)
+            .arg("-C")
+            .arg(directory)
+            .args(["cat-file", "--batch-check"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::GitNotFound(git_binary.clone()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Batch {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+impl GitBackend for GitCatFileBackend {
+    fn resolve_ref(&self, rev: &str) -> Result<Option<Commit>> {
+        // Each query is a line, which a revision can't span
+        if rev.contains('\n') {
+            return self.command.resolve_ref(rev);
+        }
+
+        let mut batch = self.batch.borrow_mut();
+        let process = match &mut *batch {
+            Some(process) => process,
+            none => none.insert(self.spawn()?),
+        };
+        let answer = process.query(&commit_spec(rev));
+        if answer.is_err() {
+            // Start over with a new process next time
+            *batch = None;
+        }
+        let answer = answer?;
+
+        // `<id> commit <size>`, or the revision followed by why it isn't found
+        if answer.ends_with(" missing") || answer.ends_with(" ambiguous") {
+            return Ok(None);
+        }
+        let id = answer.split(' ').next().unwrap_or_default();
+        Commit::parse(id).map(Some)
+    }
+
+    fn merge_base(&self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>> {
+        self.command.merge_base(lhs, rhs)
+    }
+
+    fn ahead_behind(&self, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)> {
+        self.command.ahead_behind(lhs, rhs)
+    }
+
+    fn count(&self, from: &Commit, to: &Commit) -> Result<usize> {
+        self.command.count(from, to)
+    }
+
+    fn counts(&self, pairs: &[(Commit, Commit)]) -> Result<Vec<usize>> {
+        self.command.counts(pairs)
+    }
+
+    fn commit_info(&self, commit: &Commit) -> Result<Option<CommitInfo>> {
+        self.command.commit_info(commit)
+    }
+}
+
+/// Running `git cat-file --batch-check`, answering a line per object name
+#[derive(Debug)]
+struct Batch {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Batch {
+    fn query(&mut self, name: &str) -> Result<String> {
+        writeln!(self.stdin, "{}", name)?;
+        self.stdin.flush()?;
+        let mut answer = String::new();
+        if self.stdout.read_line(&mut answer)? == 0 {
+            return Err(Error::UnexpectedOutput {
+                command: String::from("git cat-file --batch-check"),
+                output: answer,
+            });
+        }
+        Ok(answer.trim_end().to_string())
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Split `items` between a worker per available core, each running `work` on
+/// its share, and gather the results in the same order
+fn in_parallel<T: Sync, R: Send>(
+    items: &[T],
+    work: impl Fn(&[T]) -> Result<Vec<R>> + Sync,
+) -> Result<Vec<R>> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let workers = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(items.len());
+    std::thread::scope(|scope| {
+        items
+            .chunks(items.len().div_ceil(workers))
+            .map(|chunk| {
+                let work = &work;
+                scope.spawn(move || work(chunk))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|worker| worker.join().expect("backend worker panicked"))
+            .collect::<Result<Vec<Vec<_>>>>()
+            .map(|results| results.into_iter().flatten().collect())
+    })
+}
+
+/// Revision peeled to the commit it points to, except for the `:/<text>`
+/// searches which would take the suffix as part of the text, and already
+/// find commits
+fn commit_spec(rev: &str) -> String {
+    if rev.starts_with(":/") {
+        rev.to_string()
+    } else {
+        format!("{}^{{commit}}", rev)
+    }
+}
+
+/// Run a git executable in a directory, failing with its error output when
+/// it exits with an error
+pub(crate) fn run_git(
+    git_binary: &Path,
+    directory: &Path,
+    args: &[&str],
+) -> Result<std::process::Output> {
+    let mut argv = vec![OsStr::new("-C"), directory.as_os_str()];
+    argv.extend(args.iter().map(OsStr::new));
+
+    // As a plain string rather than a Path, so that duct looks a bare name up
+    // in the PATH
+    let output = match duct::cmd(git_binary.as_os_str(), argv)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::GitNotFound(git_binary.to_path_buf()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !output.status.success() {
+        return Err(Error::GitCommand {
+            command: format!("git {}", args.join(" ")),
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeBackend, Fixture};
+
+    /// Merge bases of each pair, sorted as backends don't agree on the order
+    fn sorted(mut merge_bases: Vec<Vec<Commit>>) -> Vec<Vec<Commit>> {
+        for bases in &mut merge_bases {
+            bases.sort();
+        }
+        merge_bases
+    }
+
+    #[test]
+    fn custom_backend() {
+        let fake = FakeBackend::new(&[
+            ("o", &[]),
+            ("a", &["o"]),
+            ("b", &["o"]),
+            ("c", &["a", "b"]),
+            ("d", &["b", "a"]),
+            ("x", &[]),
+        ]);
+        let id = FakeBackend::id;
+
+        assert_eq!(fake.resolve_ref("a").unwrap(), Some(id("a")));
+        assert_eq!(fake.resolve_ref("missing").unwrap(), None);
+
+        let pairs =
+            [("a", "b"), ("a", "c"), ("c", "d"), ("a", "x")].map(|(lhs, rhs)| (id(lhs), id(rhs)));
+        let expected = [vec![id("o")], vec![id("a")], vec![id("a"), id("b")], vec![]];
+        // The default merge_bases() asks for each pair in turn
+        assert_eq!(fake.merge_bases(&pairs).unwrap(), expected);
+        for (pair, bases) in pairs.iter().zip(&expected) {
+            assert_eq!(fake.merge_base(&pair.0, &pair.1).unwrap(), *bases);
+        }
+
+        assert_eq!(fake.ahead_behind(&id("c"), &id("d")).unwrap(), (1, 1));
+        assert_eq!(fake.ahead_behind(&id("a"), &id("x")).unwrap(), (2, 1));
+        // The default counts() asks for each pair in turn
+        assert_eq!(fake.counts(&pairs).unwrap(), [1, 2, 1, 1]);
+        let info = fake.commit_info(&id("c")).unwrap().unwrap();
+        assert_eq!(info.subject, "c");
+        assert_eq!(fake.commit_info(&id("missing")).unwrap(), None);
+    }
+
+    #[test]
+    fn counts_and_commit_info() {
+        let fixture = Fixture::new();
+        let root = fixture.commit("root");
+        fixture.git(&["branch", "side"]);
+        let main = fixture.commit("main");
+        fixture.git(&["switch", "--quiet", "side"]);
+        let side = fixture.commit("side");
+        let side_merge = fixture.merge(main.as_str(), "merge main");
+        let side_tip = fixture.commit("side tip");
+        fixture.git(&["switch", "--quiet", "main"]);
+        let main_merge = fixture.merge(side.as_str(), "merge side");
+        fixture.git(&["switch", "--quiet", "--orphan", "unrelated"]);
+        let unrelated = fixture.commit("unrelated");
+
+        let commits = [
+            root, main, side, side_merge, side_tip, main_merge, unrelated,
+        ];
+        let pairs = commits
+            .iter()
+            .flat_map(|lhs| commits.iter().map(move |rhs| (lhs.clone(), rhs.clone())))
+            .collect::<Vec<_>>();
+
+        let gix = GixBackend::new(gix::open(fixture.path()).unwrap(), "git");
+        let command = GitCommandBackend::new("git", fixture.path());
+        let cat_file = GitCatFileBackend::new("git", fixture.path());
+        let expected = command.counts(&pairs).unwrap();
+        assert_eq!(
+            command.ahead_behind(&commits[4], &commits[5]).unwrap(),
+            (2, 1)
+        );
+        // The commits all have the same date, which gix can't rely on to stop
+        // walking as soon as both sides meet
+        for backend in [&gix as &dyn GitBackend, &command, &cat_file] {
+            assert_eq!(backend.counts(&pairs).unwrap(), expected, "{:?}", backend);
+            for ((lhs, rhs), count) in pairs.iter().zip(&expected) {
+                let (_, behind) = backend.ahead_behind(lhs, rhs).unwrap();
+                assert_eq!(behind, *count, "{:?} {:?}...{:?}", backend, lhs, rhs);
+            }
+            for commit in &commits {
+                assert_eq!(
+                    backend.commit_info(commit).unwrap(),
+                    command.commit_info(commit).unwrap(),
+                    "{:?}",
+                    backend
+                );
+            }
+            let missing = Commit::parse(&"0".repeat(40)).unwrap();
+            assert_eq!(
+                backend.commit_info(&missing).unwrap(),
+                None,
+                "{:?}",
+                backend
+            );
+        }
+        let info = gix.commit_info(&commits[5]).unwrap().unwrap();
+        assert_eq!(info.subject, "merge side");
+    }
+
+    #[test]
+    fn batched_merge_bases() {
+        let fixture = Fixture::new();
+        let root = fixture.commit("root");
+        fixture.git(&["branch", "side"]);
+        let main = fixture.commit("main");
+        fixture.git(&["switch", "--quiet", "side"]);
+        let side = fixture.commit("side");
+        // Both branches merge the other one, a criss-cross
+        let side_merge = fixture.merge(main.as_str(), "merge main");
+        fixture.git(&["switch", "--quiet", "main"]);
+        let main_merge = fixture.merge(side.as_str(), "merge side");
+        fixture.git(&["switch", "--quiet", "--orphan", "unrelated"]);
+        let unrelated = fixture.commit("unrelated");
+
+        let commits = [root, main, side, side_merge, main_merge, unrelated];
+        let pairs = commits
+            .iter()
+            .enumerate()
+            .flat_map(|(i, lhs)| {
+                commits[i..]
+                    .iter()
+                    .map(move |rhs| (lhs.clone(), rhs.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let gix = GixBackend::new(gix::open(fixture.path()).unwrap(), "git");
+        let expected = sorted(
+            pairs
+                .iter()
+                .map(|(lhs, rhs)| gix.merge_base(lhs, rhs))
+                .collect::<Result<_>>()
+                .unwrap(),
+        );
+        let bases = |lhs: usize, rhs: usize| {
+            let pair = (commits[lhs].clone(), commits[rhs].clone());
+            &expected[pairs.iter().position(|key| *key == pair).unwrap()]
+        };
+        let mut criss_cross = vec![commits[1].clone(), commits[2].clone()];
+        criss_cross.sort();
+        assert_eq!(*bases(3, 4), criss_cross);
+        assert_eq!(*bases(0, 5), []);
+
+        let command = GitCommandBackend::new("git", fixture.path());
+        let cat_file = GitCatFileBackend::new("git", fixture.path());
+        for backend in [&gix as &dyn GitBackend, &command, &cat_file] {
+            assert_eq!(
+                sorted(backend.merge_bases(&pairs).unwrap()),
+                expected,
+                "{:?}",
+                backend
+            );
+        }
+    }
+}
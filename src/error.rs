@@ -0,0 +1,180 @@
+use std::path::PathBuf;
+
+/// Errors returned by the library
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Not a directory: {0:?}")]
+    NotADirectory(PathBuf),
+
+    #[error("Not a git directory: {0:?}")]
+    NotARepository(PathBuf),
+
+    /// A branch given explicitly doesn't exist, with the local branches of a
+    /// close name
+    #[error("Branch {branch:?} not found in {directory:?}{}", did_you_mean(.suggestions))]
+    BranchNotFound {
+        branch: String,
+        directory: PathBuf,
+        suggestions: Vec<String>,
+    },
+
+    /// None of the branches and patterns given explicitly matched anything
+    #[error("No branch found for {} in {directory:?}", quoted(.patterns))]
+    NoBranchFound {
+        patterns: Vec<String>,
+        directory: PathBuf,
+    },
+
+    /// Commit id that isn't a full hexadecimal object id
+    #[error("Invalid commit id: {0:?}")]
+    InvalidCommitId(String),
+
+    /// Branch given explicitly that is a range or a path rather than a
+    /// single revision
+    #[error("{0:?} isn't a single revision, ranges and paths aren't supported")]
+    NotARevision(String),
+
+    /// Revision given explicitly that doesn't resolve to a commit
+    #[error("Revision {0:?} not found")]
+    RevisionNotFound(String),
+
+    /// None of the refs to graph is merged into the revision given to
+    /// `--merged`
+    #[error("No branch is merged into {0:?}")]
+    NoBranchMerged(String),
+
+    /// Every ref to graph is merged into the revision given to `--no-merged`
+    #[error("Every branch is merged into {0:?}")]
+    NoBranchUnmerged(String),
+
+    /// No target was given and none of the usual default branches exists
+    #[error("Unable to determine the default branch, pass it explicitly")]
+    NoDefaultBranch,
+
+    /// None of the refs to graph contains the revision given to `--contains`
+    #[error("No branch contains {0:?}")]
+    NoBranchContains(String),
+
+    /// No node of the graph is without a parent, which can't happen with a
+    /// well-formed history
+    #[error("Unable to determine ultimate parent node")]
+    NoRoot,
+
+    #[error("Graphviz binary {0:?} not found, install Graphviz or configure its path")]
+    DotNotFound(PathBuf),
+
+    #[error("git executable {0:?} not found, install git or configure its path")]
+    GitNotFound(PathBuf),
+
+    /// A git command exited with an error
+    #[error("{command} failed ({}): {stderr}", exit_code(*.status))]
+    GitCommand {
+        command: String,
+        /// Exit code, `None` when killed by a signal
+        status: Option<i32>,
+        stderr: String,
+    },
+
+    /// A git command succeeded with an output that can't be parsed
+    #[error("Unexpected {command} output: {output:?}")]
+    UnexpectedOutput { command: String, output: String },
+
+    /// Failure of gix while reading the repository
+    #[error(transparent)]
+    Git(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    pub(crate) fn git(e: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Error::Git(Box::new(e))
+    }
+
+    pub(crate) fn boxed(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Git(e)
+    }
+}
+
+fn exit_code(status: Option<i32>) -> String {
+    match status {
+        Some(code) => format!("exit code {}", code),
+        None => String::from("killed by a signal"),
+    }
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(", did you mean {}?", quoted(suggestions))
+    }
+}
+
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{:?}", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+    use crate::Repository;
+
+    #[test]
+    fn opening_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            Repository::open(&missing).unwrap_err(),
+            Error::NotADirectory(path) if path == missing
+        ));
+        assert!(matches!(
+            Repository::open(dir.path()).unwrap_err(),
+            Error::NotARepository(path) if path == dir.path()
+        ));
+    }
+
+    #[test]
+    fn branch_errors() {
+        let fixture = Fixture::new();
+        fixture.commit("root");
+        fixture.git(&["branch", "feature"]);
+
+        let error = fixture
+            .open()
+            .with_strict(true)
+            .add_branches(["main", "featur"])
+            .unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                Error::BranchNotFound { branch, suggestions, .. }
+                    if branch == "featur" && *suggestions == ["feature"]
+            ),
+            "{:?}",
+            error
+        );
+
+        // Only fatal when nothing is left to graph
+        assert!(fixture.open().add_branches(["main", "featur"]).is_ok());
+        let error = fixture.open().add_branches(["featur"]).unwrap_err();
+        assert!(
+            matches!(&error, Error::NoBranchFound { patterns, .. } if *patterns == ["featur"]),
+            "{:?}",
+            error
+        );
+
+        let error = fixture.open().add_branches(["main..feature"]).unwrap_err();
+        assert!(
+            matches!(&error, Error::NotARevision(branch) if branch == "main..feature"),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{Commit, Error, RenderOptions};
+use colored::{ColoredString, Colorize};
+use petgraph::graph::{DiGraph, NodeIndex};
+use std::io::Write;
+
+/// Kind of a ref pointing to a node of the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub enum RefKind {
+    /// Local branch, under refs/heads
+    Branch,
+    /// Remote-tracking branch, under refs/remotes
+    Remote,
+    /// Tag, under refs/tags
+    Tag,
+    /// Detached HEAD
+    Head,
+    /// Revision given explicitly, like the commit of `--contains`
+    Revision,
+}
+
+impl RefKind {
+    /// Kind of the refs stored in the given subdirectory of refs/
+    pub(crate) fn from_dir(dir: &str) -> Self {
+        match dir {
+            "remotes" => RefKind::Remote,
+            "tags" => RefKind::Tag,
+            _ => RefKind::Branch,
+        }
+    }
+
+    /// Lowercase name, as written in GraphML and shown by the view
+    pub fn name(&self) -> &'static str {
+        match self {
+            RefKind::Branch => "branch",
+            RefKind::Remote => "remote",
+            RefKind::Tag => "tag",
+            RefKind::Head => "head",
+            RefKind::Revision => "revision",
+        }
+    }
+
+    /// Graphviz color matching the terminal color of `colorize()`
+    pub(crate) fn dot_color(&self) -> &'static str {
+        match self {
+            RefKind::Branch => "darkgreen",
+            RefKind::Remote => "darkcyan",
+            RefKind::Tag => "goldenrod",
+            RefKind::Head => "blue",
+            RefKind::Revision => "purple",
+        }
+    }
+
+    fn colorize(&self, name: &str) -> ColoredString {
+        match self {
+            RefKind::Branch => name.green(),
+            RefKind::Remote => name.cyan(),
+            RefKind::Tag => name.yellow(),
+            RefKind::Head => name.blue(),
+            RefKind::Revision => name.magenta(),
+        }
+    }
+}
+
+/// State of a local branch compared to its upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamStatus {
+    /// Number of commits only on the branch, and only on the upstream
+    Tracking { ahead: usize, behind: usize },
+    /// The upstream is configured but doesn't exist anymore
+    Gone,
+}
+
+/// Commits of a branch compared to the ref given to
+/// `Repository::with_target()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetStatus {
+    pub target: String,
+    /// Number of commits only on the branch
+    pub ahead: usize,
+    /// Number of commits only on the target
+    pub behind: usize,
+}
+
+/// Graph of the merge bases between branches, as built by
+/// `Repository::build()`
+#[derive(Debug, Clone, Default)]
+pub struct BranchGraph {
+    /// Nodes in a stable topological order, parents before their children
+    pub nodes: Vec<Node>,
+    /// Edges from the merge bases to their closest descendants
+    pub edges: Vec<Edge>,
+    /// Links from local branches to their upstream remote-tracking branch,
+    /// which aren't about ancestry
+    pub tracking: Vec<Tracking>,
+}
+
+/// Commit of the graph, either pointed to by refs or a merge base
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: Commit,
+    /// Abbreviated commit id shown in labels
+    pub short_id: String,
+    /// Whether the commit is the one checked out, through the current branch
+    /// or a detached HEAD
+    pub head: bool,
+    /// Refs pointing to the commit, sorted by name
+    pub refs: Vec<NodeRef>,
+    /// Committer date
+    pub time: Option<gix::date::Time>,
+    /// First line of the commit message, with `with_show_subject()`
+    pub subject: Option<String>,
+    /// Author name, or email with `with_show_email()`
+    pub author: Option<String>,
+    /// Formatted committer date, with `with_show_date()`
+    pub date: Option<String>,
+    /// Whether the refs haven't been committed to for longer than
+    /// `Repository::with_stale_after()`
+    pub stale: bool,
+}
+
+/// Ref pointing to a node
+#[derive(Debug, Clone)]
+pub struct NodeRef {
+    /// Short name, like `main` or `origin/main`
+    pub name: String,
+    pub kind: RefKind,
+    /// Remote of a remote-tracking branch
+    pub remote: Option<String>,
+    /// Whether it is the checked out branch or the detached HEAD
+    pub current: bool,
+    /// Remote-tracking branch configured as upstream of a local branch, like
+    /// `origin/main`
+    pub upstream_name: Option<String>,
+    /// Comparison of a local branch to its upstream, with
+    /// `with_upstream_status()`
+    pub upstream: Option<UpstreamStatus>,
+    /// Comparison of a branch to the target, with `with_target()`
+    pub target: Option<TargetStatus>,
+}
+
+/// Edge from a parent node to a child node, by index in `BranchGraph::nodes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub parent: usize,
+    pub child: usize,
+    /// Number of commits from the parent to the child, with
+    /// `with_show_distance()`
+    pub distance: Option<usize>,
+    /// Number of merge bases between the parent and the child left out by
+    /// `with_collapse_linear()`
+    pub collapsed: usize,
+}
+
+impl Edge {
+    /// Distance and number of hops, if any
+    pub fn label(&self) -> Option<String> {
+        let hops = format!("{} hops", self.collapsed + 1);
+        match (self.distance, self.collapsed) {
+            (None, 0) => None,
+            (Some(distance), 0) => Some(distance.to_string()),
+            (None, _) => Some(hops),
+            (Some(distance), _) => Some(format!("{} ({})", distance, hops)),
+        }
+    }
+}
+
+/// Local branch tracking a remote-tracking branch on another node, by index
+/// in `BranchGraph::nodes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tracking {
+    pub branch: usize,
+    pub upstream: usize,
+}
+
+impl Node {
+    /// Kind of the most prominent ref pointing to the commit, local branches
+    /// first, `None` for a merge base without refs
+    pub fn kind(&self) -> Option<RefKind> {
+        self.refs.iter().map(|r| r.kind).min()
+    }
+
+    /// Remote of the node when it is only pointed to by remote-tracking
+    /// branches of that remote
+    pub fn remote(&self) -> Option<&str> {
+        let mut remotes = self.refs.iter().map(|r| r.remote.as_deref());
+        let first = remotes.next()??;
+        remotes.all(|remote| remote == Some(first)).then_some(first)
+    }
+
+    /// Plain text label, without any color, as terminal escape sequences
+    /// are meaningless in the rendered graph
+    pub fn label(&self) -> String {
+        let mut label = self.to_string();
+        if let Some(subject) = &self.subject {
+            label.push('\n');
+            label.push_str(subject);
+        }
+        let details = [&self.author, &self.date]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !details.is_empty() {
+            label.push('\n');
+            label.push_str(&details.join(", "));
+        }
+        label
+    }
+
+    /// Label colored for the terminal
+    pub fn colored(&self) -> ColoredString {
+        self.summary(true, None).into()
+    }
+
+    /// Hash and refs on a single line, optionally colored, with only as many
+    /// refs as fit in `width` characters
+    pub(crate) fn summary(&self, color: bool, width: Option<usize>) -> String {
+        let mark = |r: &NodeRef| if r.current { "* " } else { "" };
+        let lengths = self
+            .refs
+            .iter()
+            .map(|r| {
+                format!("{}{}{}", mark(r), r.name, r.status())
+                    .chars()
+                    .count()
+            })
+            .collect::<Vec<_>>();
+
+        // Drop refs from the end until the line fits, counting the
+        // separators and the number of refs left out
+        let mut count = self.refs.len();
+        if let Some(width) = width {
+            let length = |count: usize| {
+                let more = self.refs.len() - count;
+                self.short_id.len()
+                    + lengths[..count]
+                        .iter()
+                        .map(|length| length + 2)
+                        .sum::<usize>()
+                    + if more > 0 {
+                        format!(" … +{} more", more).chars().count() + 1
+                    } else {
+                        0
+                    }
+            };
+            while count > 0 && length(count) > width {
+                count -= 1;
+            }
+        }
+
+        // Stale nodes are grayed out as a whole instead
+        let colorize = color && !self.stale;
+        let mut line = if colorize {
+            self.short_id.red().to_string()
+        } else {
+            self.short_id.clone()
+        };
+        for (i, r) in self.refs[..count].iter().enumerate() {
+            let name = match (colorize, r.current) {
+                (true, true) => r.kind.colorize(&r.name).bold().to_string(),
+                (true, false) => r.kind.colorize(&r.name).to_string(),
+                (false, _) => r.name.clone(),
+            };
+            let sep = if i == 0 { " " } else { ", " };
+            line.push_str(&format!("{}{}{}{}", sep, mark(r), name, r.status()));
+        }
+        if count < self.refs.len() {
+            let sep = if count == 0 { " " } else { ", " };
+            line.push_str(&format!("{}… +{} more", sep, self.refs.len() - count));
+        }
+
+        if color && self.stale {
+            line.bright_black().to_string()
+        } else {
+            line
+        }
+    }
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.short_id)?;
+        for (i, r) in self.refs.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            let mark = if r.current { "* " } else { "" };
+            write!(f, "{}{}{}{}", sep, mark, r.name, r.status())?;
+        }
+        Ok(())
+    }
+}
+
+impl NodeRef {
+    /// ` ↑N ↓M` ahead/behind counts of a local branch against its upstream,
+    /// if it has one
+    pub fn status(&self) -> String {
+        let mut status = match self.upstream {
+            Some(UpstreamStatus::Tracking { ahead, behind }) => format!(" ↑{} ↓{}", ahead, behind),
+            Some(UpstreamStatus::Gone) => String::from(" [gone]"),
+            None => String::new(),
+        };
+        if let Some(target) = &self.target {
+            status.push_str(&format!(
+                " [{} ↑{} ↓{}]",
+                target.target, target.ahead, target.behind
+            ));
+        }
+        status
+    }
+}
+
+/// How dates are shown in the labels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DateFormat {
+    /// How long ago, like 3 days ago
+    Relative,
+    /// Day only, like 2024-01-31
+    #[default]
+    Short,
+    /// ISO 8601, like 2024-01-31 12:34:56 +0100
+    Iso,
+}
+
+impl DateFormat {
+    pub fn format(&self, time: gix::date::Time) -> String {
+        use gix::date::time::format;
+
+        match self {
+            DateFormat::Relative => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |now| now.as_secs() as i64);
+                relative(now - time.seconds)
+            }
+            DateFormat::Short => time.format(format::SHORT),
+            DateFormat::Iso => time.format(format::ISO8601),
+        }
+    }
+}
+
+/// How long ago a number of seconds is, rounded the same way as git
+fn relative(seconds: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    let (count, unit) = match seconds {
+        ..0 => return String::from("in the future"),
+        0..90 => (seconds, "second"),
+        90..5400 => ((seconds + MINUTE / 2) / MINUTE, "minute"),
+        5400..129600 => ((seconds + HOUR / 2) / HOUR, "hour"),
+        129600..1209600 => ((seconds + DAY / 2) / DAY, "day"),
+        1209600..6048000 => ((seconds + 7 * DAY / 2) / (7 * DAY), "week"),
+        6048000..31536000 => ((seconds + 15 * DAY) / (30 * DAY), "month"),
+        _ => ((seconds + 365 * DAY / 2) / (365 * DAY), "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+impl BranchGraph {
+    /// Same graph as a petgraph one, for its algorithms
+    ///
+    /// The node indexes are the ones of `nodes`, and the edges are weighted
+    /// with their distance. Tracking links are left out as they aren't about
+    /// ancestry.
+    pub fn to_petgraph(&self) -> DiGraph<Node, Option<usize>> {
+        let mut graph = DiGraph::with_capacity(self.nodes.len(), self.edges.len());
+        for node in &self.nodes {
+            graph.add_node(node.clone());
+        }
+        for edge in &self.edges {
+            graph.add_edge(
+                NodeIndex::new(edge.parent),
+                NodeIndex::new(edge.child),
+                edge.distance,
+            );
+        }
+        graph
+    }
+
+    /// Write the graph in the format of the options, see
+    /// `RenderOptions::renderer()`
+    pub fn render(&self, options: &RenderOptions, out: &mut dyn Write) -> Result<(), Error> {
+        options.renderer().render(self, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{node, FakeBackend};
+    use crate::Format;
+
+    #[test]
+    fn render_into_vec() {
+        let graph = BranchGraph {
+            nodes: vec![
+                node(FakeBackend::id("o"), &[]),
+                node(FakeBackend::id("a"), &["main", "topic"]),
+            ],
+            edges: vec![Edge {
+                parent: 0,
+                child: 1,
+                distance: Some(3),
+                collapsed: 0,
+            }],
+            tracking: Vec::new(),
+        };
+
+        let mut out = Vec::new();
+        graph.render(&RenderOptions::default(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph {\n\
+             \t0 [label=\"6f00000\"]\n\
+             \t1 [label=\"6100000 main, topic\", color=darkgreen, fontcolor=darkgreen]\n\
+             \t0 -> 1 [label=\"3\"]\n\
+             }\n"
+        );
+
+        let options = RenderOptions {
+            format: Format::Ascii,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        graph.render(&options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "6f00000\n`-- 6100000 main, topic\n"
+        );
+    }
+}
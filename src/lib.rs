@@ -0,0 +1,34 @@
+//! Graph of the merge bases between git branches.
+//!
+//! ```no_run
+//! use git_branch_graph::{RenderOptions, Repository};
+//!
+//! let graph = Repository::open(".")?
+//!     .with_remote(true)
+//!     .add_branches(["main", "feature/*"])?
+//!     .build()?;
+//! graph.render(&RenderOptions::default(), &mut std::io::stdout())?;
+//! # Ok::<(), git_branch_graph::Error>(())
+//! ```
+
+mod backend;
+mod error;
+mod graph;
+mod render;
+mod repository;
+#[cfg(test)]
+mod testing;
+
+pub use backend::{CommitInfo, GitBackend, GitCatFileBackend, GitCommandBackend, GixBackend};
+pub use error::Error;
+pub use graph::{
+    BranchGraph, DateFormat, Edge, Node, NodeRef, RefKind, TargetStatus, Tracking, UpstreamStatus,
+};
+pub use render::{
+    D2Renderer, DotRenderer, Format, GraphmlRenderer, ImageRenderer, JsonRenderer, MermaidRenderer,
+    PlantumlRenderer, RankDir, RenderOptions, Renderer, TreeRenderer,
+};
+pub use repository::{Commit, Repository, Stats};
+
+/// Version of petgraph returned by `BranchGraph::to_petgraph()`
+pub use petgraph;
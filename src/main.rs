@@ -1,30 +1,401 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
-mod repository;
-use repository::Repository;
+mod tui;
+
+use git_branch_graph::{
+    DateFormat, Format, GitCatFileBackend, RankDir, RefKind, Repository, Stats,
+};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{IsTerminal, Write};
 
 #[derive(Default, Parser)]
-#[command(version, infer_subcommands = true)]
+#[command(version, infer_subcommands = true, after_help = EXIT_STATUS)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(flatten)]
-    pub verbose: clap_verbosity_flag::Verbosity,
+    pub verbose: clap_verbosity_flag::Verbosity<clap_verbosity_flag::WarnLevel>,
 
-    /// Path to the git repository
+    /// Path to the git repository, its worktree or a bare repository
+    ///
+    /// Ignored when GIT_DIR is set, which is used as the git directory.
     #[arg(short = 'C', long, value_name = "PATH")]
     pub directory: Option<PathBuf>,
 
-    /// List remote branches
+    /// Also graph the upstream remote-tracking branch of each local branch
+    ///
+    /// The upstream is read from the branch.<name>.remote and
+    /// branch.<name>.merge configuration.
     #[arg(short, long)]
     pub remote: bool,
 
-    /// Branches
+    /// Graph every local branch, remote-tracking branch and tag
+    #[arg(short, long, conflicts_with = "configured_only")]
+    pub all: bool,
+
+    /// Also graph the commits pointed to by tags, optionally only those
+    /// matching a glob pattern
+    #[arg(
+        short,
+        long,
+        value_name = "PATTERN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "*"
+    )]
+    pub tags: Option<String>,
+
+    /// Also graph HEAD when it is detached
+    #[arg(long)]
+    pub head: bool,
+
+    /// Show how many commits each local branch is ahead and behind its
+    /// upstream
+    #[arg(short, long)]
+    pub upstream_status: bool,
+
+    /// Only graph the branches with a branch.<name> config section
+    ///
+    /// By default every local branch is graphed when none is given.
+    #[arg(long)]
+    pub configured_only: bool,
+
+    /// Leave out the discovered refs matching this glob pattern
+    ///
+    /// Can be given multiple times. Branches given explicitly by name are
+    /// always included.
+    #[arg(short = 'x', long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Only graph the branches merged into this ref
+    #[arg(long, value_name = "REF")]
+    pub merged: Option<String>,
+
+    /// Only graph the branches not merged into this ref
+    #[arg(long, value_name = "REF")]
+    pub no_merged: Option<String>,
+
+    /// Show how many commits each branch is ahead and behind this ref
+    #[arg(long, value_name = "REF")]
+    pub target: Option<String>,
+
+    /// Only graph the branches containing this commit, and the commit itself
+    #[arg(long, value_name = "COMMIT")]
+    pub contains: Option<String>,
+
+    /// Gray out the branches without commits for this long, like 90d, 6w or
+    /// 1y
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub stale_after: Option<Duration>,
+
+    /// Only explore the merge bases this many levels away from the branches,
+    /// collapsing the older ones into a single root
+    ///
+    /// This is an approximation for huge repositories, an older merge base
+    /// may be the parent of more nodes than shown.
+    #[arg(long, value_name = "N")]
+    pub depth: Option<usize>,
+
+    /// Keep the edges implied by longer paths, to see every merge base
+    /// relation computed
+    #[arg(long)]
+    pub no_prune: bool,
+
+    /// Leave out the merge bases with a single parent and a single child,
+    /// labeling the edge replacing them with the number of hops
+    #[arg(long)]
+    pub collapse_linear: bool,
+
+    /// Only graph the N branches with the most recent commits
+    ///
+    /// This is an approximation for huge repositories, the date of the last
+    /// commit doesn't tell whether a branch is still in use.
+    #[arg(long, value_name = "N")]
+    pub max_branches: Option<usize>,
+
+    /// Abort when a branch given explicitly doesn't exist, instead of
+    /// skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Abbreviate commit ids to this many characters
+    ///
+    /// Defaults to core.abbrev from the git config, or 7. Shorter than 4
+    /// counts as 4, like git.
+    #[arg(long, value_name = "N")]
+    pub abbrev: Option<usize>,
+
+    /// Show full commit ids
+    #[arg(long, visible_alias = "no-abbrev", conflicts_with = "abbrev")]
+    pub full_hash: bool,
+
+    /// Show the first line of the commit messages in the labels
+    #[arg(long)]
+    pub show_subject: bool,
+
+    /// Truncate the subjects to this many characters
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    pub subject_width: usize,
+
+    /// Show the author of the commits in the labels
+    #[arg(long)]
+    pub show_author: bool,
+
+    /// Show the email of the authors instead of their name, implies
+    /// --show-author
+    #[arg(long)]
+    pub show_email: bool,
+
+    /// Show the committer date of the commits in the labels
+    #[arg(long)]
+    pub show_date: bool,
+
+    /// How to show the dates
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t)]
+    pub date_format: DateFormat,
+
+    /// Show the dates in UTC instead of the local time zone
+    #[arg(long)]
+    pub utc: bool,
+
+    /// Label the edges with the number of commits between their nodes
+    #[arg(long, visible_alias = "edge-counts")]
+    pub show_distance: bool,
+
+    /// Output format [default: dot, or svg with --open]
+    ///
+    /// The svg, png and pdf formats run Graphviz on the DOT output, which
+    /// --render is an alias for.
+    #[arg(short, long, visible_alias = "render", value_enum)]
+    pub format: Option<Format>,
+
+    /// When to color the terminal output
+    ///
+    /// Colors are never written into the graph itself. NO_COLOR is honoured
+    /// by default.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t)]
+    pub color: clap::ColorChoice,
+
+    /// Same as --color=never
+    #[arg(long, conflicts_with = "color")]
+    pub no_color: bool,
+
+    /// Git executable to run instead of git from the PATH
+    #[arg(long, value_name = "PATH")]
+    pub git_binary: Option<PathBuf>,
+
+    /// How the revisions are resolved and the merge bases computed
+    #[arg(long, value_enum, default_value_t)]
+    pub backend: Backend,
+
+    /// Graphviz binary used to render the svg, png and pdf formats
+    ///
+    /// Defaults to the branch-graph.dotBinary git config, or dot from the
+    /// PATH.
+    #[arg(long, value_name = "PATH")]
+    pub dot_binary: Option<PathBuf>,
+
+    /// Render the checked out commit like any other node
+    #[arg(long)]
+    pub no_highlight_head: bool,
+
+    /// Group the branches in DOT clusters by the leading segments of their
+    /// name, one unless given a depth
+    ///
+    /// Nodes with several refs are grouped by the first name in alphabetical
+    /// order. Instead of the clusters of remote-tracking branches.
+    #[arg(long, value_name = "prefix[:DEPTH]", value_parser = parse_cluster)]
+    pub cluster: Option<usize>,
+
+    /// Title shown at the top of the DOT graph, the name of the repository
+    /// directory unless given
+    #[arg(
+        long,
+        value_name = "TEXT",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    pub title: Option<Option<String>>,
+
+    /// Shape of the DOT nodes, like box [default: ellipse]
+    #[arg(long, value_name = "SHAPE")]
+    pub node_shape: Option<String>,
+
+    /// Style of the DOT nodes, like rounded or filled, comma separated
+    ///
+    /// Kept along the dashed remote-tracking branches and the bold checked
+    /// out commit.
+    #[arg(long, value_name = "STYLE")]
+    pub node_style: Option<String>,
+
+    /// Direction of the DOT layout [default: TB]
+    #[arg(long, value_name = "DIRECTION", value_enum, ignore_case = true)]
+    pub rankdir: Option<RankDir>,
+
+    /// Graphviz attribute of the whole graph, like splines=ortho
+    ///
+    /// Can be repeated, the attributes are only added to the DOT output.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_attribute)]
+    pub graph_attr: Vec<(String, String)>,
+
+    /// Default Graphviz attribute of the nodes, like fontname=Helvetica
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_attribute)]
+    pub node_attr: Vec<(String, String)>,
+
+    /// Default Graphviz attribute of the edges, like arrowsize=0.5
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_attribute)]
+    pub edge_attr: Vec<(String, String)>,
+
+    /// Write the graph to this file instead of the standard output
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Compute every merge base again instead of reusing the cached ones,
+    /// and don't save them
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Open the graph with the default viewer
+    ///
+    /// The graph is written to a temporary file named after the repository,
+    /// unless --output is given.
+    #[arg(long)]
+    pub open: bool,
+
+    /// Also read branches from the standard input, separated by whitespace
+    ///
+    /// Blank lines and comments starting with # are skipped.
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Branches to graph, or glob patterns matching them
+    ///
+    /// Remote-tracking branches, tags and revisions like commit ids or
+    /// main~2 are shown as such, but not ranges or paths.
     pub branches: Vec<String>,
 }
 
-fn main() -> Result<()> {
-    use clap::error::ErrorKind::*;
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage the cache of merge bases kept in the git directory
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// List the branches fully merged into a target, without deleting
+    /// anything
+    ///
+    /// The branches are the ones that would be graphed, so --remote also
+    /// lists the merged upstreams and --all every merged remote-tracking
+    /// branch.
+    Merged {
+        /// Branch the others are merged into [default: the one origin/HEAD
+        /// points to, init.defaultBranch, main or master]
+        target: Option<String>,
+
+        /// Print the git commands deleting the merged branches instead of
+        /// their names
+        #[arg(long)]
+        print_delete_commands: bool,
+    },
+
+    /// Browse the graph interactively in the terminal
+    ///
+    /// Arrows move through the list, or to the first parent and child of the
+    /// selected node, y copies its commit id and q quits.
+    View,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Delete the cache, for instance when it is corrupted
+    Clear,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// In-process, running git only for the revisions gix can't parse
+    #[default]
+    Gix,
+    /// A single git cat-file process for the revisions, and git merge-base
+    /// for each pair of branches
+    Git,
+}
+
+const EXIT_STATUS: &str = "\
+Exit status:
+  0  Success
+  1  Other failure
+  2  Invalid arguments
+  3  Not a git repository
+  4  Branch or revision not found
+  5  git failed";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match e.downcast_ref::<clap::Error>() {
+                Some(e) => {
+                    let _ = e.print();
+                }
+                None => {
+                    eprintln!("Error: {:?}", e);
+                    if let Some(hint) = hint(&e) {
+                        eprintln!("hint: {}", hint);
+                    }
+                }
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// How to fix the error with the command line, which the library can't
+/// know about
+fn hint(e: &anyhow::Error) -> Option<&'static str> {
+    use git_branch_graph::Error;
+
+    match e.downcast_ref::<Error>()? {
+        Error::DotNotFound(_) => {
+            Some("pass --dot-binary or set branch-graph.dotBinary to the path of dot")
+        }
+        Error::GitNotFound(_) => Some("pass --git-binary with the path of git"),
+        _ => None,
+    }
+}
+
+/// Exit status telling scripts what went wrong, as listed in `EXIT_STATUS`
+fn exit_code(e: &anyhow::Error) -> u8 {
+    use git_branch_graph::Error;
+
+    if e.is::<clap::Error>() {
+        return 2;
+    }
+    match e.downcast_ref::<Error>() {
+        Some(Error::NotARevision(_)) => 2,
+        Some(Error::NotADirectory(_) | Error::NotARepository(_)) => 3,
+        Some(
+            Error::BranchNotFound { .. } | Error::NoBranchFound { .. } | Error::RevisionNotFound(_),
+        ) => 4,
+        Some(
+            Error::GitNotFound(_)
+            | Error::GitCommand { .. }
+            | Error::UnexpectedOutput { .. }
+            | Error::Git(_),
+        ) => 5,
+        _ => 1,
+    }
+}
+
+fn run() -> Result<()> {
+    use clap::error::ErrorKind::{DisplayHelp, DisplayVersion};
 
     let cli = match Cli::try_parse_from(std::env::args_os()) {
         Ok(cli) => cli,
@@ -39,13 +410,321 @@ fn main() -> Result<()> {
         },
     };
 
-    setup_log(cli.verbose.log_level_filter())?;
+    let color = if cli.no_color {
+        clap::ColorChoice::Never
+    } else {
+        cli.color
+    };
+    match color {
+        clap::ColorChoice::Always => colored::control::set_override(true),
+        clap::ColorChoice::Never => colored::control::set_override(false),
+        clap::ColorChoice::Auto => {
+            // colored checks whether stdout is a terminal, but the colors only
+            // go into the log messages on stderr. It handles NO_COLOR and
+            // CLICOLOR_FORCE itself
+            if std::env::var_os("NO_COLOR").is_none()
+                && std::env::var_os("CLICOLOR_FORCE").is_none()
+            {
+                colored::control::set_override(std::io::stderr().is_terminal());
+            }
+        }
+    }
+    setup_log(cli.verbose.log_level_filter(), color)?;
+
+    let directory = match cli.directory {
+        Some(directory) => directory,
+        None => std::env::current_dir()?,
+    };
+    let repo = Repository::open(directory)?;
+
+    if let Some(Command::Cache {
+        command: CacheCommand::Clear,
+    }) = cli.command
+    {
+        if !repo.clear_cache()? {
+            log::info!("No cache to clear in {:?}", repo.git_dir());
+        }
+        return Ok(());
+    }
+
+    let mut branches = cli.branches;
+    if cli.stdin {
+        let read = read_branches(std::io::stdin().lock())?;
+        // Unlike no argument at all, which graphs every branch
+        if read.is_empty() && branches.is_empty() {
+            anyhow::bail!("No branch read from the standard input");
+        }
+        branches.extend(read);
+    }
+
+    // The details pane has room for everything
+    let viewing = matches!(cli.command, Some(Command::View));
+    if viewing && !std::io::stdout().is_terminal() {
+        anyhow::bail!("The view subcommand needs a terminal");
+    }
+    let repo = match cli.backend {
+        Backend::Gix => repo,
+        Backend::Git => {
+            let git_binary = cli
+                .git_binary
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("git"));
+            let backend = GitCatFileBackend::new(git_binary, repo.directory());
+            repo.with_backend(backend)
+        }
+    };
+    let progress = progress_bar(cli.verbose.log_level_filter() < log::LevelFilter::Warn);
+    let repo = match progress.clone() {
+        Some(progress) => repo.with_progress(report_progress(progress)),
+        None => repo,
+    };
+    let mut repo = repo
+        .with_cache(!cli.no_cache)
+        .with_git_binary(cli.git_binary)
+        .with_remote(cli.remote)
+        .with_all(cli.all)
+        .with_tags(cli.tags)
+        .with_configured_only(cli.configured_only)
+        .with_head(cli.head)
+        .with_upstream_status(cli.upstream_status || viewing)
+        .with_exclude(cli.exclude)
+        .with_strict(cli.strict)
+        .with_contains(cli.contains)
+        .with_merged(cli.merged)
+        .with_no_merged(cli.no_merged)
+        .with_target(cli.target)
+        .with_stale_after(cli.stale_after)
+        .with_depth(cli.depth)
+        .with_max_branches(cli.max_branches)
+        .with_prune(!cli.no_prune)
+        .with_collapse_linear(cli.collapse_linear)
+        .with_show_subject(cli.show_subject || viewing)
+        .with_subject_width(cli.subject_width)
+        .with_show_author(cli.show_author || viewing)
+        .with_show_email(cli.show_email)
+        .with_show_date(cli.show_date || viewing)
+        .with_date_format(cli.date_format)
+        .with_utc(cli.utc)
+        .with_show_distance(cli.show_distance)
+        .with_abbrev(if cli.full_hash {
+            Some(usize::MAX)
+        } else {
+            cli.abbrev
+        })
+        .add_branches(&branches)?;
+
+    if let Some(Command::Merged {
+        target,
+        print_delete_commands,
+    }) = cli.command
+    {
+        let mut stdout = std::io::stdout().lock();
+        for r in repo.merged(target.as_deref())? {
+            if !print_delete_commands {
+                writeln!(stdout, "{}", r.name)?;
+                continue;
+            }
+            match (r.kind, r.name.split_once('/')) {
+                (RefKind::Branch, _) => writeln!(stdout, "git branch -d {}", shell_quote(&r.name))?,
+                (RefKind::Remote, Some((remote, branch))) => writeln!(
+                    stdout,
+                    "git push {} --delete {}",
+                    shell_quote(remote),
+                    shell_quote(branch)
+                )?,
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+
+    let graph = repo.build();
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+    let graph = graph?;
+    if viewing {
+        return Ok(tui::run(&graph)?);
+    }
+
+    let mut options = repo.render_options();
+    // Opening a DOT file is rarely useful, so --open shows an image unless
+    // told otherwise
+    options.format = cli
+        .format
+        .unwrap_or(if cli.open { Format::Svg } else { Format::Dot });
+    if let Some(dot_binary) = cli.dot_binary {
+        options.dot_binary = dot_binary;
+    }
+    options.highlight_head = !cli.no_highlight_head;
+    options.title = cli.title.map(|title| {
+        title.unwrap_or_else(|| {
+            let name = repo.directory().file_name().unwrap_or_default();
+            let name = name.to_string_lossy();
+            name.strip_suffix(".git").unwrap_or(&name).to_string()
+        })
+    });
+    options.cluster_prefix = cli.cluster;
+    options.node_shape = cli.node_shape;
+    options.node_style = cli.node_style;
+    options.rankdir = cli.rankdir;
+    options.graph_attributes = cli.graph_attr;
+    options.node_attributes = cli.node_attr;
+    options.edge_attributes = cli.edge_attr;
 
-    Repository::try_from(cli)?.run()
+    let output = match cli.output {
+        None if cli.open => Some(open_path(&repo, options.format)),
+        output => output,
+    };
+    // Only the tree formats are colored, and cut to the terminal width
+    if output.is_none() && std::io::stdout().is_terminal() {
+        options.color = color != clap::ColorChoice::Never;
+        options.width = terminal_size::terminal_size().map(|(width, _)| usize::from(width.0));
+    } else {
+        options.color = color == clap::ColorChoice::Always;
+    }
+    // Rendered in memory first, so that a failure never leaves a partial
+    // graph behind, and written to a temporary file for the same reason
+    let mut rendered = Vec::new();
+    graph.render(&options, &mut rendered)?;
+    match &output {
+        Some(path) => {
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            std::fs::write(&tmp, &rendered)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        None => std::io::stdout().lock().write_all(&rendered)?,
+    }
+    if let (true, Some(path)) = (cli.open, &output) {
+        if let Err(e) = opener::open(path) {
+            anyhow::bail!("Unable to open {:?}: {}", path, e);
+        }
+    }
+
+    Ok(())
 }
 
-fn setup_log(level: log::LevelFilter) -> Result<()> {
-    use env_logger::{Builder, Env};
+/// Parse a number of hours, days, weeks, months or years, like `90d`
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    const DAY: u64 = 24 * 60 * 60;
+
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (count, unit) = duration.split_at(split);
+    let count = count
+        .parse::<u64>()
+        .map_err(|_| format!("{:?} doesn't start with a number", duration))?;
+    let unit = match unit {
+        "h" => 60 * 60,
+        "d" => DAY,
+        "w" => 7 * DAY,
+        "m" => 30 * DAY,
+        "y" => 365 * DAY,
+        _ => return Err(format!("Unknown unit {:?}, expected h, d, w, m or y", unit)),
+    };
+
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{:?} is too long", duration))
+}
+
+/// Branch names separated by whitespace, skipping the comments, from a word
+/// starting with `#` to the end of the line as `#` is valid within ref names
+fn read_branches(input: impl std::io::BufRead) -> std::io::Result<Vec<String>> {
+    let mut branches = Vec::new();
+    for line in input.lines() {
+        branches.extend(
+            line?
+                .split_whitespace()
+                .take_while(|word| !word.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    Ok(branches)
+}
+
+/// Parse a clustering mode, only `prefix` with an optional depth for now
+fn parse_cluster(mode: &str) -> Result<usize, String> {
+    let depth = match mode.split_once(':') {
+        Some(("prefix", depth)) => depth
+            .parse()
+            .map_err(|_| format!("Invalid depth {:?}", depth))?,
+        None if mode == "prefix" => 1,
+        _ => return Err(format!("Unknown mode {:?}, expected prefix[:DEPTH]", mode)),
+    };
+    if depth == 0 {
+        return Err(String::from("The depth must be at least 1"));
+    }
+    Ok(depth)
+}
+
+/// Parse a Graphviz attribute given as `key=value`
+fn parse_attribute(attribute: &str) -> Result<(String, String), String> {
+    match attribute.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("{:?} isn't of the form key=value", attribute)),
+    }
+}
+
+/// Quote a ref name for a POSIX shell, if needed
+fn shell_quote(name: &str) -> String {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+@".contains(c))
+    {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "'\\''"))
+    }
+}
+
+/// Temporary file the graph is written to with `--open`, the same for every
+/// run on a repository so viewers can reload it
+fn open_path(repo: &Repository, format: Format) -> PathBuf {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    repo.git_dir().hash(&mut hasher);
+    std::env::temp_dir().join(format!(
+        "git-branch-graph-{:016x}.{}",
+        hasher.finish(),
+        format.extension()
+    ))
+}
+
+/// Progress bar on stderr, hidden until `report_progress()` shows it, none
+/// when quiet, off a terminal or logging to the journal
+fn progress_bar(quiet: bool) -> Option<ProgressBar> {
+    if quiet || !std::io::stderr().is_terminal() || systemd_journal_logger::connected_to_journal() {
+        return None;
+    }
+    let style = ProgressStyle::with_template("{spinner} {msg}").expect("valid template");
+    Some(ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden()).with_style(style))
+}
+
+/// Show the work done on the progress bar, only once the build has taken
+/// more than a second so quick runs stay silent
+fn report_progress(progress: ProgressBar) -> impl FnMut(&Stats) {
+    let start = std::time::Instant::now();
+    let mut visible = false;
+    move |stats| {
+        if !visible && start.elapsed() > Duration::from_secs(1) {
+            visible = true;
+            progress.set_draw_target(ProgressDrawTarget::stderr());
+            progress.enable_steady_tick(Duration::from_millis(100));
+        }
+        progress.set_message(format!(
+            "Processed {} nodes, {} merge-base queries ({} cached)",
+            stats.nodes, stats.merge_bases, stats.cached
+        ));
+    }
+}
+
+fn setup_log(level: log::LevelFilter, color: clap::ColorChoice) -> Result<()> {
+    use env_logger::{Builder, Env, WriteStyle};
     use systemd_journal_logger::{connected_to_journal, JournalLog};
 
     // If the output streams of this process are directly connected to the
@@ -66,6 +745,11 @@ fn setup_log(level: log::LevelFilter) -> Result<()> {
 
         Builder::new()
             .filter_level(log::LevelFilter::Trace)
+            .write_style(match color {
+                clap::ColorChoice::Always => WriteStyle::Always,
+                clap::ColorChoice::Never => WriteStyle::Never,
+                clap::ColorChoice::Auto => WriteStyle::Auto,
+            })
             .parse_env(env)
             .try_init()?;
     }
@@ -74,3 +758,49 @@ fn setup_log(level: log::LevelFilter) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        const DAY: u64 = 24 * 60 * 60;
+        for (duration, seconds) in [
+            ("12h", 12 * 60 * 60),
+            ("30d", 30 * DAY),
+            ("6w", 42 * DAY),
+            ("3m", 90 * DAY),
+            ("1y", 365 * DAY),
+            ("0d", 0),
+        ] {
+            assert_eq!(
+                parse_duration(duration),
+                Ok(Duration::from_secs(seconds)),
+                "{}",
+                duration
+            );
+        }
+        for duration in [
+            "",
+            "d",
+            "30",
+            "30x",
+            "-1d",
+            "1.5y",
+            "30 d",
+            "99999999999999999999d",
+        ] {
+            assert!(parse_duration(duration).is_err(), "{}", duration);
+        }
+    }
+
+    #[test]
+    fn read_branches_skips_comments() {
+        let input = "# branches to review\nfix#12 main # merged\n  feature/a\tfeature/b\n#main\n";
+        assert_eq!(
+            read_branches(input.as_bytes()).unwrap(),
+            ["fix#12", "main", "feature/a", "feature/b"]
+        );
+    }
+}
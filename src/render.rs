@@ -0,0 +1,1070 @@
+use crate::{BranchGraph, Error, Node, RefKind};
+use duct::cmd;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Writer of the graph in one of the output formats
+pub trait Renderer {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error>;
+}
+
+/// Output format of the graph
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+    /// JSON document listing nodes and edges
+    Json,
+    /// GraphML document, for Gephi or yEd
+    Graphml,
+    /// PlantUML object diagram
+    Plantuml,
+    /// D2 diagram
+    D2,
+    /// Indented tree for the terminal
+    Tree,
+    /// Indented tree drawn with ASCII characters only
+    Ascii,
+    /// SVG image, rendered by Graphviz
+    Svg,
+    /// PNG image, rendered by Graphviz
+    Png,
+    /// PDF document, rendered by Graphviz
+    Pdf,
+}
+
+impl Format {
+    /// Usual extension of the files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Mermaid => "mmd",
+            Format::Json => "json",
+            Format::Graphml => "graphml",
+            Format::Plantuml => "puml",
+            Format::D2 => "d2",
+            Format::Tree | Format::Ascii => "txt",
+            Format::Svg => "svg",
+            Format::Png => "png",
+            Format::Pdf => "pdf",
+        }
+    }
+}
+
+/// Direction of the Graphviz layout, from the parents to their children
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "UPPER")]
+pub enum RankDir {
+    /// Top to bottom
+    #[default]
+    Tb,
+    /// Left to right
+    Lr,
+    /// Bottom to top
+    Bt,
+    /// Right to left
+    Rl,
+}
+
+impl std::fmt::Display for RankDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RankDir::Tb => "TB",
+            RankDir::Lr => "LR",
+            RankDir::Bt => "BT",
+            RankDir::Rl => "RL",
+        })
+    }
+}
+
+/// How `BranchGraph::render()` writes the graph
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub format: Format,
+    /// Graphviz binary rendering the images
+    pub dot_binary: PathBuf,
+    /// Whether the checked out commit stands out from the other nodes
+    pub highlight_head: bool,
+    /// Whether the tree formats are colored for the terminal
+    pub color: bool,
+    /// Number of columns the lines of the tree formats are cut to
+    pub width: Option<usize>,
+    /// Label shown at the top of the DOT graph
+    pub title: Option<String>,
+    /// Number of leading path segments of the ref names the DOT nodes are
+    /// clustered by, instead of clustering the remote-tracking branches by
+    /// remote
+    pub cluster_prefix: Option<usize>,
+    /// Default shape of the DOT nodes, like `box`, Graphviz' ellipse when
+    /// `None`
+    pub node_shape: Option<String>,
+    /// Default style of the DOT nodes, like `rounded,filled`, kept along the
+    /// styles of the remote, stale and checked out nodes
+    pub node_style: Option<String>,
+    /// Direction of the DOT layout, Graphviz' default when `None`
+    pub rankdir: Option<RankDir>,
+    /// Attributes of the DOT graph, node defaults and edge defaults, passed
+    /// through as is
+    pub graph_attributes: Vec<(String, String)>,
+    pub node_attributes: Vec<(String, String)>,
+    pub edge_attributes: Vec<(String, String)>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            format: Default::default(),
+            dot_binary: PathBuf::from("dot"),
+            highlight_head: true,
+            color: false,
+            width: None,
+            title: None,
+            cluster_prefix: None,
+            node_shape: None,
+            node_style: None,
+            rankdir: None,
+            graph_attributes: Vec::new(),
+            node_attributes: Vec::new(),
+            edge_attributes: Vec::new(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Renderer of the format, following the other options
+    pub fn renderer(&self) -> Box<dyn Renderer + '_> {
+        match self.format {
+            Format::Dot => Box::new(DotRenderer { options: self }),
+            Format::Mermaid => Box::new(MermaidRenderer { options: self }),
+            Format::Json => Box::new(JsonRenderer),
+            Format::Graphml => Box::new(GraphmlRenderer),
+            Format::Plantuml => Box::new(PlantumlRenderer { options: self }),
+            Format::D2 => Box::new(D2Renderer { options: self }),
+            Format::Tree | Format::Ascii => Box::new(TreeRenderer { options: self }),
+            Format::Svg | Format::Png | Format::Pdf => Box::new(ImageRenderer { options: self }),
+        }
+    }
+}
+
+/// Graphviz DOT graph
+#[derive(Debug, Clone, Copy)]
+pub struct DotRenderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for DotRenderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        writeln!(out, "digraph {{")?;
+        if let Some(title) = &self.options.title {
+            writeln!(out, "\tlabel=\"{}\"", escape_quoted(title))?;
+            writeln!(out, "\tlabelloc=t")?;
+        }
+        if let Some(rankdir) = self.options.rankdir {
+            writeln!(out, "\trankdir={}", rankdir)?;
+        }
+        write_dot_attributes(out, "graph", &self.options.graph_attributes)?;
+        let node_attributes = [
+            ("shape", &self.options.node_shape),
+            ("style", &self.options.node_style),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value.clone()?)))
+        .chain(self.options.node_attributes.iter().cloned())
+        .collect::<Vec<_>>();
+        write_dot_attributes(out, "node", &node_attributes)?;
+        write_dot_attributes(out, "edge", &self.options.edge_attributes)?;
+        // Group the nodes only pointed to by the branches of one remote, to
+        // keep them apart from the local branches, or by the prefix of their
+        // first ref name when asked to
+        let mut clusters = BTreeMap::<&str, Vec<usize>>::new();
+        for (id, node) in graph.nodes.iter().enumerate() {
+            let cluster = match self.options.cluster_prefix {
+                Some(depth) => node
+                    .refs
+                    .iter()
+                    .map(|r| r.name.as_str())
+                    .min()
+                    .and_then(|name| name_prefix(name, depth)),
+                None => node.remote(),
+            };
+            match cluster {
+                Some(cluster) => clusters.entry(cluster).or_default().push(id),
+                None => write_dot_node(out, "\t", id, node, self.options)?,
+            }
+        }
+        for (cluster, ids) in clusters {
+            writeln!(out, "\tsubgraph \"cluster_{}\" {{", escape_quoted(cluster))?;
+            writeln!(out, "\t\tlabel=\"{}\"", escape_quoted(cluster))?;
+            if self.options.cluster_prefix.is_none() {
+                writeln!(out, "\t\tcolor=darkcyan")?;
+                writeln!(out, "\t\tfontcolor=darkcyan")?;
+            }
+            for id in ids {
+                write_dot_node(out, "\t\t", id, &graph.nodes[id], self.options)?;
+            }
+            writeln!(out, "\t}}")?;
+        }
+        for edge in &graph.edges {
+            match edge.label() {
+                Some(label) => writeln!(
+                    out,
+                    "\t{} -> {} [label=\"{}\"]",
+                    edge.parent, edge.child, label
+                )?,
+                None => writeln!(out, "\t{} -> {}", edge.parent, edge.child)?,
+            }
+        }
+        for tracking in &graph.tracking {
+            writeln!(
+                out,
+                "\t{} -> {} [style=dashed, color=darkcyan, arrowhead=open, constraint=false]",
+                tracking.branch, tracking.upstream
+            )?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+}
+
+/// Image of the DOT graph, rendered by Graphviz in the svg, png or pdf
+/// format of the options
+#[derive(Debug, Clone, Copy)]
+pub struct ImageRenderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for ImageRenderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        let mut dot = Vec::new();
+        DotRenderer {
+            options: self.options,
+        }
+        .render(graph, &mut dot)?;
+
+        let dot_binary = &self.options.dot_binary;
+        // duct runs a relative Path from the current directory, pass it as a
+        // plain string to look it up in the PATH like a shell
+        let image = match cmd!(
+            dot_binary.as_os_str(),
+            format!("-T{}", self.options.format.extension())
+        )
+        .stdin_bytes(dot)
+        .stdout_capture()
+        .run()
+        {
+            Ok(output) => output.stdout,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::DotNotFound(dot_binary.to_path_buf()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        out.write_all(&image)?;
+
+        Ok(())
+    }
+}
+
+/// Mermaid flowchart
+#[derive(Debug, Clone, Copy)]
+pub struct MermaidRenderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for MermaidRenderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        writeln!(out, "graph TD")?;
+        for (id, node) in graph.nodes.iter().enumerate() {
+            writeln!(out, "\tn{}[\"{}\"]", id, mermaid_escape(&node.label()))?;
+            if node.stale {
+                writeln!(
+                    out,
+                    "\tstyle n{} stroke:gray,color:gray,stroke-dasharray:5 5",
+                    id
+                )?;
+            } else if node.kind() == Some(RefKind::Remote) {
+                writeln!(out, "\tstyle n{} stroke-dasharray:5 5", id)?;
+            }
+            if self.options.highlight_head && node.head {
+                writeln!(out, "\tstyle n{} fill:lightyellow,stroke-width:3px", id)?;
+            }
+        }
+        for edge in &graph.edges {
+            match edge.label() {
+                Some(label) => writeln!(
+                    out,
+                    "\tn{} -->|\"{}\"| n{}",
+                    edge.parent,
+                    mermaid_escape(&label),
+                    edge.child
+                )?,
+                None => writeln!(out, "\tn{} --> n{}", edge.parent, edge.child)?,
+            }
+        }
+        for tracking in &graph.tracking {
+            writeln!(out, "\tn{} -.-> n{}", tracking.branch, tracking.upstream)?;
+        }
+        Ok(())
+    }
+}
+
+/// PlantUML object diagram
+#[derive(Debug, Clone, Copy)]
+pub struct PlantumlRenderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for PlantumlRenderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        writeln!(out, "@startuml")?;
+        for (id, node) in graph.nodes.iter().enumerate() {
+            write!(
+                out,
+                "object \"{}\" as n{}",
+                plantuml_escape(&node.label()),
+                id
+            )?;
+            if self.options.highlight_head && node.head {
+                write!(out, " #lightyellow")?;
+            }
+            writeln!(out)?;
+        }
+        for edge in &graph.edges {
+            match edge.label() {
+                Some(label) => writeln!(out, "n{} --> n{} : {}", edge.parent, edge.child, label)?,
+                None => writeln!(out, "n{} --> n{}", edge.parent, edge.child)?,
+            }
+        }
+        for tracking in &graph.tracking {
+            writeln!(out, "n{} ..> n{}", tracking.branch, tracking.upstream)?;
+        }
+        writeln!(out, "@enduml")?;
+        Ok(())
+    }
+}
+
+/// D2 diagram
+#[derive(Debug, Clone, Copy)]
+pub struct D2Renderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for D2Renderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        for (id, node) in graph.nodes.iter().enumerate() {
+            write!(out, "n{}: \"{}\"", id, escape_quoted(&node.label()))?;
+            let mut style = Vec::new();
+            if node.stale {
+                style.extend(["style.stroke: gray", "style.font-color: gray"]);
+            }
+            if node.stale || node.kind() == Some(RefKind::Remote) {
+                style.push("style.stroke-dash: 3");
+            }
+            if self.options.highlight_head && node.head {
+                style.extend(["style.fill: lightyellow", "style.stroke-width: 3"]);
+            }
+            if !style.is_empty() {
+                write!(out, " {{{}}}", style.join("; "))?;
+            }
+            writeln!(out)?;
+        }
+        for edge in &graph.edges {
+            match edge.label() {
+                Some(label) => writeln!(
+                    out,
+                    "n{} -> n{}: \"{}\"",
+                    edge.parent,
+                    edge.child,
+                    escape_quoted(&label)
+                )?,
+                None => writeln!(out, "n{} -> n{}", edge.parent, edge.child)?,
+            }
+        }
+        for tracking in &graph.tracking {
+            writeln!(
+                out,
+                "n{} -> n{}: {{style.stroke-dash: 3}}",
+                tracking.branch, tracking.upstream
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the graph as an indented tree from each root, like `tree`
+///
+/// Nodes with several parents are only written in full under the first
+/// one, and referenced under the other ones.
+#[derive(Debug, Clone, Copy)]
+pub struct TreeRenderer<'a> {
+    pub options: &'a RenderOptions,
+}
+
+impl Renderer for TreeRenderer<'_> {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        let mut children = vec![Vec::new(); graph.nodes.len()];
+        let mut roots = vec![true; graph.nodes.len()];
+        for edge in &graph.edges {
+            children[edge.parent].push(edge.child);
+            roots[edge.child] = false;
+        }
+
+        let mut written = vec![false; graph.nodes.len()];
+        for root in (0..graph.nodes.len()).filter(|root| roots[*root]) {
+            let mut tree = Tree {
+                out: &mut *out,
+                options: self.options,
+                children: &children,
+                written: &mut written,
+            };
+            tree.write(graph, root)?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON document listing the nodes and edges
+#[derive(Debug, Clone, Copy)]
+pub struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        let json = JsonGraph {
+            nodes: graph
+                .nodes
+                .iter()
+                .map(|node| JsonNode {
+                    id: node.id.as_str(),
+                    short_id: &node.short_id,
+                    branches: node.refs.iter().map(|r| r.name.as_str()).collect(),
+                    date: node
+                        .time
+                        .map(|time| time.format(gix::date::time::format::ISO8601_STRICT)),
+                    stale: node.stale,
+                    subject: node.subject.as_deref(),
+                    author: node.author.as_deref(),
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .iter()
+                .map(|edge| JsonEdge {
+                    parent: graph.nodes[edge.parent].id.as_str(),
+                    child: graph.nodes[edge.child].id.as_str(),
+                    distance: edge.distance,
+                    collapsed: edge.collapsed,
+                })
+                .collect(),
+        };
+
+        serde_json::to_writer_pretty(&mut *out, &json).map_err(std::io::Error::from)?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Write the graph as GraphML, with the ids, branches and kind of the
+/// nodes as attributes
+#[derive(Debug, Clone, Copy)]
+pub struct GraphmlRenderer;
+
+impl Renderer for GraphmlRenderer {
+    fn render(&self, graph: &BranchGraph, out: &mut dyn Write) -> Result<(), Error> {
+        let mut writer = quick_xml::Writer::new_with_indent(&mut *out, b' ', 2);
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        writer
+            .create_element("graphml")
+            .with_attributes([
+                ("xmlns", "http://graphml.graphdrawing.org/xmlns"),
+                ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+                (
+                    "xsi:schemaLocation",
+                    "http://graphml.graphdrawing.org/xmlns \
+                     http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd",
+                ),
+            ])
+            .write_inner_content(|writer| {
+                graphml_key(writer, "node", "id", "string")?;
+                graphml_key(writer, "node", "short_id", "string")?;
+                graphml_key(writer, "node", "branches", "string")?;
+                graphml_key(writer, "node", "kind", "string")?;
+                graphml_key(writer, "node", "label", "string")?;
+                graphml_key(writer, "edge", "distance", "int")?;
+                graphml_key(writer, "edge", "collapsed", "int")?;
+
+                writer
+                    .create_element("graph")
+                    .with_attributes([("id", "branches"), ("edgedefault", "directed")])
+                    .write_inner_content(|writer| {
+                        for (id, node) in graph.nodes.iter().enumerate() {
+                            let branches = node
+                                .refs
+                                .iter()
+                                .map(|r| r.name.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            writer
+                                .create_element("node")
+                                .with_attribute(("id", format!("n{}", id).as_str()))
+                                .write_inner_content(|writer| {
+                                    graphml_data(writer, "id", node.id.as_str())?;
+                                    graphml_data(writer, "short_id", &node.short_id)?;
+                                    graphml_data(writer, "branches", &branches)?;
+                                    if let Some(kind) = node.kind() {
+                                        graphml_data(writer, "kind", kind.name())?;
+                                    }
+                                    graphml_data(writer, "label", &node.label())
+                                })?;
+                        }
+                        for edge in &graph.edges {
+                            let element = writer.create_element("edge").with_attributes([
+                                ("source", format!("n{}", edge.parent).as_str()),
+                                ("target", format!("n{}", edge.child).as_str()),
+                            ]);
+                            if edge.distance.is_none() && edge.collapsed == 0 {
+                                element.write_empty()?;
+                                continue;
+                            }
+                            element.write_inner_content(|writer| {
+                                if let Some(distance) = edge.distance {
+                                    graphml_data(writer, "distance", &distance.to_string())?;
+                                }
+                                if edge.collapsed > 0 {
+                                    graphml_data(writer, "collapsed", &edge.collapsed.to_string())?;
+                                }
+                                Ok(())
+                            })?;
+                        }
+                        Ok(())
+                    })?;
+                Ok(())
+            })?;
+
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Graph as serialized by `--format json`
+#[derive(serde::Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct JsonNode<'a> {
+    /// Full commit id
+    id: &'a str,
+    /// Abbreviated commit id
+    short_id: &'a str,
+    /// Branches pointing to the commit, sorted by name
+    branches: Vec<&'a str>,
+    /// Committer date, in RFC 3339 format
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<&'a str>,
+    /// Author name or email, as shown in the labels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a str>,
+    stale: bool,
+}
+
+/// Edge from a merge base to a descendant node, by commit id
+#[derive(serde::Serialize)]
+struct JsonEdge<'a> {
+    parent: &'a str,
+    child: &'a str,
+    /// Number of commits from the parent to the child
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<usize>,
+    /// Number of merge bases left out between the parent and the child
+    #[serde(skip_serializing_if = "is_zero")]
+    collapsed: usize,
+}
+
+/// State of `TreeRenderer` while walking down the graph
+struct Tree<'a> {
+    out: &'a mut dyn Write,
+    options: &'a RenderOptions,
+    children: &'a [Vec<usize>],
+    written: &'a mut [bool],
+}
+
+impl Tree<'_> {
+    /// Write a root and its descendants, depth first with an explicit stack
+    /// of the nodes left to write, so that a long chain of merge bases
+    /// doesn't overflow the call stack
+    fn write(&mut self, graph: &BranchGraph, root: usize) -> std::io::Result<()> {
+        let ascii = self.options.format == Format::Ascii;
+        // Node, prefix of its line, and whether it is the last child of its
+        // parent, if any
+        let mut stack = vec![(root, String::new(), None)];
+        while let Some((id, prefix, last)) = stack.pop() {
+            let (branch, indent) = match last {
+                None => ("", ""),
+                Some(false) if ascii => ("|-- ", "|   "),
+                Some(true) if ascii => ("`-- ", "    "),
+                Some(false) => ("├── ", "│   "),
+                Some(true) => ("└── ", "    "),
+            };
+            let node = &graph.nodes[id];
+            if self.written[id] {
+                writeln!(
+                    self.out,
+                    "{}{}(see above: {})",
+                    prefix, branch, node.short_id
+                )?;
+                continue;
+            }
+            self.written[id] = true;
+
+            let width = self
+                .options
+                .width
+                .map(|width| width.saturating_sub(prefix.chars().count() + branch.chars().count()));
+            let mut line = node.summary(self.options.color, width);
+            // The subject and details go on the same line
+            for details in node.label().lines().skip(1) {
+                line.push_str("  ");
+                line.push_str(details);
+            }
+            writeln!(self.out, "{}{}{}", prefix, branch, line)?;
+
+            // Pushed in reverse to pop the first child first
+            let prefix = format!("{}{}", prefix, indent);
+            let children = &self.children[id];
+            for (i, child) in children.iter().enumerate().rev() {
+                stack.push((*child, prefix.clone(), Some(i + 1 == children.len())));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Declare a GraphML attribute of the nodes or edges
+fn graphml_key<W: Write>(
+    writer: &mut quick_xml::Writer<W>,
+    target: &str,
+    name: &str,
+    kind: &str,
+) -> std::io::Result<()> {
+    writer
+        .create_element("key")
+        .with_attributes([
+            ("id", name),
+            ("for", target),
+            ("attr.name", name),
+            ("attr.type", kind),
+        ])
+        .write_empty()?;
+    Ok(())
+}
+
+/// Write the value of a GraphML attribute
+fn graphml_data<W: Write>(
+    writer: &mut quick_xml::Writer<W>,
+    key: &str,
+    value: &str,
+) -> std::io::Result<()> {
+    writer
+        .create_element("data")
+        .with_attribute(("key", key))
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+/// Write a DOT node statement, colored and styled after its refs
+fn write_dot_node(
+    out: &mut dyn Write,
+    indent: &str,
+    id: usize,
+    node: &Node,
+    options: &RenderOptions,
+) -> std::io::Result<()> {
+    write!(
+        out,
+        "{}{} [label=\"{}\"",
+        indent,
+        id,
+        escape_quoted(&node.label())
+    )?;
+    // Merge bases without refs keep the default black
+    let kind = node.kind();
+    if let Some(kind) = kind {
+        let color = if node.stale { "gray" } else { kind.dot_color() };
+        write!(out, ", color={}, fontcolor={}", color, color)?;
+    }
+    let mut style = Vec::new();
+    if node.stale || kind == Some(RefKind::Remote) {
+        style.push("dashed");
+    }
+    if options.highlight_head && node.head {
+        style.extend(["bold", "filled"]);
+    }
+    if !style.is_empty() {
+        // Replacing the default style, which has to be repeated
+        let mut styles = options
+            .node_style
+            .iter()
+            .flat_map(|node_style| node_style.split(',').map(str::trim))
+            .collect::<Vec<_>>();
+        for s in style {
+            if !styles.contains(&s) {
+                styles.push(s);
+            }
+        }
+        write!(out, ", style=\"{}\"", styles.join(","))?;
+    }
+    if options.highlight_head && node.head {
+        write!(out, ", fillcolor=lightyellow")?;
+    }
+    writeln!(out, "]")
+}
+
+/// First `depth` segments of a ref name, if it has more, like `team` for
+/// `team/feature/x` at depth 1
+fn name_prefix(name: &str, depth: usize) -> Option<&str> {
+    let (end, _) = name.match_indices('/').nth(depth.checked_sub(1)?)?;
+    Some(&name[..end])
+}
+
+/// Default attributes of the graph, nodes or edges, skipped when empty
+fn write_dot_attributes(
+    out: &mut dyn Write,
+    statement: &str,
+    attributes: &[(String, String)],
+) -> std::io::Result<()> {
+    if attributes.is_empty() {
+        return Ok(());
+    }
+    let attributes = attributes
+        .iter()
+        .map(|(key, value)| format!("\"{}\"=\"{}\"", escape_quoted(key), escape_quoted(value)))
+        .collect::<Vec<_>>();
+    writeln!(out, "\t{} [{}]", statement, attributes.join(", "))
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+/// Escape characters with a special meaning in a C-like double-quoted
+/// string, as in DOT and D2, and drop the ANSI escape sequences, which a
+/// commit subject may contain but no renderer understands. D2 takes the
+/// backslash escapes of DOT, not entities like Mermaid, which it would show
+/// as is
+fn escape_quoted(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // A control sequence runs from `ESC [` up to its final byte, in
+            // @ to ~, any other escape is dropped alone
+            '\x1b' => {
+                let mut sequence = chars.clone();
+                if sequence.next() == Some('[') {
+                    chars = sequence;
+                    chars.find(|c| ('@'..='~').contains(c));
+                }
+            }
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape characters with a special meaning in a quoted PlantUML name, which
+/// has no escape for quotes but understands HTML entities
+fn plantuml_escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("&#34;"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape characters with a special meaning in a quoted Mermaid label
+fn mermaid_escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '\n' => escaped.push_str("<br>"),
+            '#' => escaped.push_str("#35;"),
+            '/' => escaped.push_str("#47;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '[' => escaped.push_str("#91;"),
+            ']' => escaped.push_str("#93;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::node;
+    use crate::{Commit, Edge, Tracking};
+
+    /// Graph of a single local branch, at a made-up commit
+    fn branch(name: &str) -> BranchGraph {
+        BranchGraph {
+            nodes: vec![node(Commit::parse(&"1".repeat(40)).unwrap(), &[name])],
+            edges: Vec::new(),
+            tracking: Vec::new(),
+        }
+    }
+
+    fn render(format: Format, graph: &BranchGraph) -> String {
+        let options = RenderOptions {
+            format,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        graph.render(&options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escape_quoted_strings() {
+        for (label, escaped) in [
+            ("main", "main"),
+            ("weird\"name", "weird\\\"name"),
+            ("back\\slash", "back\\\\slash"),
+            ("two\nlines", "two\\nlines"),
+            ("crlf\r\n", "crlf\\r\\n"),
+            ("héllo/日本語 🌿", "héllo/日本語 🌿"),
+            ("\x1b[1;31mred\x1b[0m", "red"),
+            ("\x1b[Kerased", "erased"),
+            ("lone\x1b escape", "lone escape"),
+        ] {
+            assert_eq!(escape_quoted(label), escaped, "{:?}", label);
+        }
+    }
+
+    #[test]
+    fn plantuml_escape_names() {
+        for (label, escaped) in [
+            ("feature/a", "feature/a"),
+            ("weird\"name", "weird&#34;name"),
+            ("back\\slash", "back\\\\slash"),
+            ("two\nlines", "two\\nlines"),
+            ("héllo 日本語", "héllo 日本語"),
+        ] {
+            assert_eq!(plantuml_escape(label), escaped, "{:?}", label);
+        }
+    }
+
+    #[test]
+    fn mermaid_escape_labels() {
+        for (label, escaped) in [
+            ("main", "main"),
+            ("weird\"name", "weird#quot;name"),
+            ("feature/a", "feature#47;a"),
+            ("issue#1", "issue#35;1"),
+            ("<tag>", "#lt;tag#gt;"),
+            ("[wip]", "#91;wip#93;"),
+            ("two\nlines", "two<br>lines"),
+            ("héllo 日本語", "héllo 日本語"),
+        ] {
+            assert_eq!(mermaid_escape(label), escaped, "{:?}", label);
+        }
+    }
+
+    #[test]
+    fn mermaid_quotes_branch_names() {
+        let graph = BranchGraph {
+            nodes: vec![
+                node(Commit::parse(&"1".repeat(40)).unwrap(), &["main"]),
+                node(Commit::parse(&"2".repeat(40)).unwrap(), &["feature/\"x\""]),
+            ],
+            edges: vec![Edge {
+                parent: 0,
+                child: 1,
+                distance: Some(3),
+                collapsed: 1,
+            }],
+            tracking: Vec::new(),
+        };
+        assert_eq!(
+            render(Format::Mermaid, &graph),
+            "graph TD\n\
+             \tn0[\"1111111 main\"]\n\
+             \tn1[\"2222222 feature#47;#quot;x#quot;\"]\n\
+             \tn0 -->|\"3 (2 hops)\"| n1\n"
+        );
+    }
+
+    #[test]
+    fn d2_styles_and_quotes_nodes() {
+        let mut stale = node(Commit::parse(&"2".repeat(40)).unwrap(), &["back\\slash\"q"]);
+        stale.stale = true;
+        let mut upstream = node(Commit::parse(&"3".repeat(40)).unwrap(), &["origin/main"]);
+        upstream.refs[0].kind = RefKind::Remote;
+        upstream.refs[0].remote = Some("origin".to_string());
+        let edge = |child| Edge {
+            parent: 0,
+            child,
+            distance: None,
+            collapsed: 0,
+        };
+        let graph = BranchGraph {
+            nodes: vec![
+                node(Commit::parse(&"1".repeat(40)).unwrap(), &["main"]),
+                stale,
+                upstream,
+            ],
+            edges: vec![edge(1), edge(2)],
+            tracking: vec![Tracking {
+                branch: 0,
+                upstream: 2,
+            }],
+        };
+        assert_eq!(
+            render(Format::D2, &graph),
+            "n0: \"1111111 main\"\n\
+             n1: \"2222222 back\\\\slash\\\"q\" \
+             {style.stroke: gray; style.font-color: gray; style.stroke-dash: 3}\n\
+             n2: \"3333333 origin/main\" {style.stroke-dash: 3}\n\
+             n0 -> n1\n\
+             n0 -> n2\n\
+             n0 -> n2: {style.stroke-dash: 3}\n"
+        );
+    }
+
+    #[test]
+    fn tree_references_written_nodes() {
+        let edge = |parent, child| Edge {
+            parent,
+            child,
+            distance: None,
+            collapsed: 0,
+        };
+        let graph = BranchGraph {
+            nodes: ["a", "b", "c", "d"]
+                .iter()
+                .zip(1..)
+                .map(|(name, i)| node(Commit::parse(&i.to_string().repeat(40)).unwrap(), &[name]))
+                .collect(),
+            edges: vec![edge(0, 1), edge(0, 2), edge(1, 3), edge(2, 3)],
+            tracking: Vec::new(),
+        };
+        assert_eq!(
+            render(Format::Tree, &graph),
+            "1111111 a\n\
+             ├── 2222222 b\n\
+             │   └── 4444444 d\n\
+             └── 3333333 c\n\
+             \x20   └── (see above: 4444444)\n"
+        );
+        assert_eq!(
+            render(Format::Ascii, &graph),
+            "1111111 a\n\
+             |-- 2222222 b\n\
+             |   `-- 4444444 d\n\
+             `-- 3333333 c\n\
+             \x20   `-- (see above: 4444444)\n"
+        );
+    }
+
+    #[test]
+    fn tree_of_a_long_chain() {
+        const LENGTH: usize = 2000;
+        let graph = BranchGraph {
+            nodes: (0..LENGTH)
+                .map(|i| {
+                    node(
+                        Commit::parse(&format!("{:07x}{}", i, "0".repeat(33))).unwrap(),
+                        &[],
+                    )
+                })
+                .collect(),
+            edges: (1..LENGTH)
+                .map(|child| Edge {
+                    parent: child - 1,
+                    child,
+                    distance: None,
+                    collapsed: 0,
+                })
+                .collect(),
+            tracking: Vec::new(),
+        };
+        // Far less stack than a call per level would need
+        let tree = std::thread::Builder::new()
+            .stack_size(128 * 1024)
+            .spawn(move || render(Format::Tree, &graph))
+            .unwrap()
+            .join()
+            .unwrap();
+        let lines = tree.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), LENGTH);
+        assert_eq!(lines[0], format!("{:07x}", 0));
+        assert_eq!(
+            lines[LENGTH - 1],
+            format!("{}└── {:07x}", "    ".repeat(LENGTH - 2), LENGTH - 1)
+        );
+    }
+
+    #[test]
+    fn graphml_round_trip() {
+        let name = "a&<>\"b";
+        let xml = render(Format::Graphml, &branch(name));
+
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let mut data = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(element) if element.name().as_ref() == "data" => {
+                    let key = element.try_get_attribute("key").unwrap().unwrap();
+                    let text = reader.read_text(element.name()).unwrap();
+                    data.push((
+                        key.normalized_value(Default::default())
+                            .unwrap()
+                            .into_owned(),
+                        quick_xml::escape::unescape(&text).unwrap().into_owned(),
+                    ));
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        let id = "1".repeat(40);
+        let label = format!("1111111 {}", name);
+        let expected = [
+            ("id", id.as_str()),
+            ("short_id", "1111111"),
+            ("branches", name),
+            ("kind", "branch"),
+            ("label", label.as_str()),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn dot_quotes_branch_names() {
+        assert_eq!(
+            render(Format::Dot, &branch("weird\"name")),
+            "digraph {\n\
+             \t0 [label=\"1111111 weird\\\"name\", color=darkgreen, fontcolor=darkgreen]\n\
+             }\n"
+        );
+    }
+}
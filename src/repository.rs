@@ -1,330 +1,2460 @@
-use crate::Cli;
-use anyhow::Result;
-use colored::{ColoredString, Colorize};
-use duct::cmd;
-use std::collections::{HashMap, HashSet, LinkedList};
-use std::path::PathBuf;
+use crate::backend::{GitBackend, GixBackend};
+use crate::{
+    BranchGraph, DateFormat, Edge, Error, Node, NodeRef, RefKind, RenderOptions, TargetStatus,
+    Tracking, UpstreamStatus,
+};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, LinkedList};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Number of characters of the commit ids shown in labels, unless
+/// `core.abbrev` says otherwise, the same default as git
+const ABBREV: usize = 7;
+
+/// Shortest abbreviation accepted by `core.abbrev`
+const MIN_ABBREV: usize = 4;
+
+/// Default number of characters of the subjects shown, a common limit for
+/// the first line of commit messages
+const SUBJECT_WIDTH: usize = 50;
+
+/// Ref graphed on a node, by short name and kind, as a local branch may have
+/// the same short name as a remote-tracking branch or a tag
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Ref {
+    name: String,
+    kind: RefKind,
+}
+
+/// Full id of a commit
 #[derive(Debug, Clone, derive_more::From, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Commit(String);
 
-pub struct CommitDisplay<'a>(&'a Commit, &'a Repository);
+impl Commit {
+    /// Commit with a full SHA-1 or SHA-256 hexadecimal id, as printed by git
+    pub fn parse(id: &str) -> Result<Self> {
+        if matches!(id.len(), 40 | 64) && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Commit(id.to_ascii_lowercase()))
+        } else {
+            Err(Error::InvalidCommitId(id.to_string()))
+        }
+    }
+
+    /// Object id of the commit in the object database
+    pub fn id(&self) -> Result<gix::ObjectId> {
+        gix::ObjectId::from_hex(self.0.as_bytes())
+            .map_err(|_| Error::InvalidCommitId(self.0.clone()))
+    }
+
+    /// Full id, in hexadecimal
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Id abbreviated to at most `len` characters
+    pub fn short(&self, len: usize) -> &str {
+        match self.0.char_indices().nth(len) {
+            Some((end, _)) => &self.0[..end],
+            None => &self.0,
+        }
+    }
+}
+
+struct CommitDisplay<'a>(&'a Commit, &'a Repository);
 
 impl std::fmt::Display for CommitDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.1.name(self.0))
+        write!(f, "{}", self.1.node(self.0).colored())
     }
 }
 
-#[derive(Debug)]
-pub struct Repository {
-    pub directory: PathBuf,
-    pub config: gix_config::File<'static>,
-    pub remote: bool,
-    pub branch_names: Vec<String>,
-    pub id_to_branches: HashMap<Commit, HashSet<String>>,
-    pub nodes_to_children: HashMap<Commit, HashSet<Commit>>,
-    pub nodes_to_parents: HashMap<Commit, HashSet<Commit>>,
-    pub merge_bases: HashMap<(Commit, Commit), Commit>,
+/// Merge bases and distances kept between runs, as they never change for
+/// given commits
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct MergeBaseCache {
+    merge_bases: Vec<CachedMergeBase>,
+    #[serde(default)]
+    distances: Vec<CachedDistance>,
 }
 
-impl TryFrom<Cli> for Repository {
-    type Error = anyhow::Error;
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedMergeBase {
+    lhs: String,
+    rhs: String,
+    bases: Vec<String>,
+}
 
-    fn try_from(cli: Cli) -> Result<Self> {
-        let directory = if let Some(dir) = cli.directory {
-            if !dir.is_dir() {
-                anyhow::bail!("Not a directory: {:?}", dir);
-            }
-            dir
-        } else {
-            std::env::current_dir()?
-        };
+impl CachedMergeBase {
+    /// Key and value of the `merge_bases` map, checking that the ids weren't
+    /// tampered with
+    fn parse(&self) -> Result<((Commit, Commit), Vec<Commit>)> {
+        let key = (Commit::parse(&self.lhs)?, Commit::parse(&self.rhs)?);
+        let bases = self
+            .bases
+            .iter()
+            .map(|base| Commit::parse(base))
+            .collect::<Result<_>>()?;
 
-        let mut repo = Repository::new(directory)?;
-        repo.remote = cli.remote;
+        Ok((key, bases))
+    }
+}
 
-        for branch in cli.branches {
-            repo.add_branch("heads", branch)?;
-        }
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedDistance {
+    parent: String,
+    child: String,
+    count: usize,
+}
+
+impl CachedDistance {
+    /// Key and value of the `distances` map, checking that the ids weren't
+    /// tampered with
+    fn parse(&self) -> Result<((Commit, Commit), usize)> {
+        let key = (Commit::parse(&self.parent)?, Commit::parse(&self.child)?);
+
+        Ok((key, self.count))
+    }
+}
+
+/// Work done building the graph so far, as reported to
+/// `Repository::with_progress()`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Nodes compared to every other node
+    pub nodes: usize,
+    /// Pairs of nodes whose merge bases were computed or read from the cache
+    pub merge_bases: usize,
+    /// Those read from the cache file
+    pub cached: usize,
+}
+
+/// Callback of `Repository::with_progress()`
+struct Progress(Box<dyn FnMut(&Stats)>);
 
-        Ok(repo)
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress")
     }
 }
 
+/// Git repository whose branches are graphed.
+///
+/// The `with_*()` options apply to the branches added afterwards, so they
+/// come before `add_branches()`.
+#[derive(Debug)]
+pub struct Repository {
+    directory: PathBuf,
+    git: gix::Repository,
+    config: gix_config::File<'static>,
+    remote: bool,
+    all: bool,
+    /// Glob pattern of the tags to graph, if any
+    tags: Option<String>,
+    configured_only: bool,
+    head: bool,
+    upstream_status: bool,
+    strict: bool,
+    /// Revision every graphed ref must contain, if any
+    contains: Option<String>,
+    /// Revision every graphed ref must be merged into, if any
+    merged: Option<String>,
+    /// Revision no graphed ref may be merged into, if any
+    no_merged: Option<String>,
+    /// Age of the last commit past which a ref is stale
+    stale_after: Option<Duration>,
+    /// Number of levels of merge bases explored from the refs, if limited
+    depth: Option<usize>,
+    /// Number of refs graphed, the most recent ones, if limited
+    max_branches: Option<usize>,
+    /// Whether the edges implied by longer paths are dropped
+    prune: bool,
+    /// Whether the merge bases with one parent and one child are left out
+    collapse_linear: bool,
+    /// Number of merge bases left out on the edges, with `collapse_linear`
+    collapsed: HashMap<(Commit, Commit), usize>,
+    show_subject: bool,
+    /// Number of characters past which subjects are truncated
+    subject_width: usize,
+    show_author: bool,
+    /// Whether the author is shown by email rather than name
+    show_email: bool,
+    show_date: bool,
+    date_format: DateFormat,
+    /// Time zone the dates are shown in
+    time_zone: jiff::tz::TimeZone,
+    /// Number of characters of the abbreviated commit ids
+    abbrev: usize,
+    /// Git executable run for what gix doesn't do
+    git_binary: PathBuf,
+    /// Backend resolving revisions and computing merge bases, a `GixBackend`
+    /// created on first use unless given with `with_backend()`
+    backend: OnceCell<Box<dyn GitBackend>>,
+    /// Short name of the checked out branch, `None` when HEAD is detached
+    current_branch: Option<String>,
+    /// Checked out commit, `None` on an unborn branch
+    head_id: Option<Commit>,
+    exclude: Vec<String>,
+    branch_names: Vec<String>,
+    id_to_branches: HashMap<Commit, BTreeSet<Ref>>,
+    /// Local branches compared to their upstream, by name
+    upstream_statuses: HashMap<String, UpstreamStatus>,
+    /// Ref the branches are compared to, if any
+    target: Option<String>,
+    target_statuses: HashMap<Ref, TargetStatus>,
+    nodes_to_children: HashMap<Commit, HashSet<Commit>>,
+    nodes_to_parents: HashMap<Commit, HashSet<Commit>>,
+    merge_bases: HashMap<(Commit, Commit), Vec<Commit>>,
+    show_distance: bool,
+    /// Number of commits between the parent and the child of edges
+    distances: HashMap<(Commit, Commit), usize>,
+    /// Whether `merge_bases` is read from and saved to the cache file
+    cache: bool,
+    /// Whether `merge_bases` has entries missing from the cache file
+    cache_dirty: bool,
+    /// Merge bases read from the cache file not counted in `stats` yet
+    cached: HashSet<(Commit, Commit)>,
+    stats: Stats,
+    /// Called whenever `stats` changes
+    progress: Option<Progress>,
+    /// Nodes of the graph in the order they are rendered
+    order: Vec<Commit>,
+}
+
 impl Repository {
-    pub fn new(directory: PathBuf) -> Result<Self> {
-        let refs_dir = directory.join(".git/refs/heads");
-        if !refs_dir.is_dir() {
-            anyhow::bail!("Not a git directory: {:?}", directory);
+    /// Open the repository containing `directory`
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self, Error> {
+        let directory = directory.into();
+        if !directory.is_dir() {
+            return Err(Error::NotADirectory(directory));
         }
 
-        let config = gix_config::File::from_git_dir(directory.join(".git"))?;
+        // Let gix find the enclosing repository the way git does, walking up
+        // from `directory` but not above GIT_CEILING_DIRECTORIES, and resolving
+        // the actual git directory: the directory itself for a bare
+        // repository, or whatever `.git` points to in a linked worktree.
+        // Don't look for refs/heads, which may legitimately be missing once
+        // every ref has been packed. GIT_DIR, when set, is used as is like git
+        // does, with GIT_WORK_TREE or the current directory as the worktree.
+        // The walk only goes up the components of the path it is given, so a
+        // relative `directory` would stop short of the enclosing repository
+        let absolute = std::path::absolute(&directory)?;
+        let options = gix::discover::upwards::Options {
+            match_ceiling_dir_or_error: false,
+            ..Default::default()
+        };
+        let git = match gix::ThreadSafeRepository::discover_with_environment_overrides_opts(
+            &absolute,
+            options,
+            Default::default(),
+        ) {
+            Ok(git) => {
+                let mut git = git.to_thread_local();
+                // Merge bases are computed over and over on the same commits
+                git.object_cache_size_if_unset(4 * 1024 * 1024);
+                git
+            }
+            Err(e) => {
+                log::debug!("gix::discover({:?}): {}", directory, e);
+                let directory = std::env::var_os("GIT_DIR").map_or(directory, PathBuf::from);
+                return Err(Error::NotARepository(directory));
+            }
+        };
+        let directory = git.work_dir().unwrap_or(git.git_dir()).to_path_buf();
 
-        Ok(Repository {
+        // The config and the refs live in the common directory, shared by
+        // every worktree
+        let config =
+            gix_config::File::from_git_dir(git.common_dir().to_path_buf()).map_err(Error::git)?;
+
+        let current_branch = git
+            .head_name()
+            .map_err(Error::git)?
+            .map(|name| name.shorten().to_string());
+        let head_id = git.head_id().ok().map(|id| Commit(id.to_string()));
+
+        let mut repository = Repository {
             directory,
+            git,
             config,
             remote: false,
+            all: false,
+            tags: None,
+            configured_only: false,
+            head: false,
+            upstream_status: false,
+            strict: false,
+            contains: None,
+            merged: None,
+            no_merged: None,
+            stale_after: None,
+            depth: None,
+            max_branches: None,
+            prune: true,
+            collapse_linear: false,
+            collapsed: Default::default(),
+            show_subject: false,
+            subject_width: SUBJECT_WIDTH,
+            show_author: false,
+            show_email: false,
+            show_date: false,
+            date_format: Default::default(),
+            time_zone: jiff::tz::TimeZone::system(),
+            abbrev: ABBREV,
+            git_binary: PathBuf::from("git"),
+            backend: OnceCell::new(),
+            current_branch,
+            head_id,
+            exclude: Default::default(),
             branch_names: Default::default(),
             id_to_branches: Default::default(),
+            upstream_statuses: Default::default(),
+            target: None,
+            target_statuses: Default::default(),
             nodes_to_children: Default::default(),
             nodes_to_parents: Default::default(),
             merge_bases: Default::default(),
-        })
+            show_distance: false,
+            distances: Default::default(),
+            cache: true,
+            cache_dirty: false,
+            cached: Default::default(),
+            stats: Default::default(),
+            progress: None,
+            order: Default::default(),
+        };
+        repository.abbrev = repository.config_abbrev();
+
+        Ok(repository)
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        if self.branch_names.is_empty() {
-            self.read_branches()?;
+    /// Also graph the upstream remote-tracking branch of each local branch
+    pub fn with_remote(mut self, remote: bool) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Graph every local branch, remote-tracking branch and tag when no
+    /// branch is added
+    pub fn with_all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Also graph the tags matching a glob pattern
+    pub fn with_tags(mut self, pattern: Option<String>) -> Self {
+        self.tags = pattern;
+        self
+    }
+
+    /// Only graph the branches with a `branch.<name>` config section when no
+    /// branch is added
+    pub fn with_configured_only(mut self, configured_only: bool) -> Self {
+        self.configured_only = configured_only;
+        self
+    }
+
+    /// Also graph HEAD when it is detached
+    pub fn with_head(mut self, head: bool) -> Self {
+        self.head = head;
+        self
+    }
+
+    /// Compare each local branch to its upstream
+    pub fn with_upstream_status(mut self, upstream_status: bool) -> Self {
+        self.upstream_status = upstream_status;
+        self
+    }
+
+    /// Leave out the discovered refs matching these glob patterns, including
+    /// the ones matching a pattern given to `add_branches()`, but not the
+    /// branches given to it by name
+    pub fn with_exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+
+    /// Fail on branches that don't exist in `add_branches()` instead of
+    /// skipping them
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Abbreviate commit ids to `len` characters instead of following
+    /// `core.abbrev`, at least 4 like git
+    pub fn with_abbrev(mut self, len: Option<usize>) -> Self {
+        if let Some(len) = len {
+            self.abbrev = len.max(MIN_ABBREV);
         }
+        self
+    }
 
-        let mut new_nodes = self
-            .id_to_branches
-            .keys()
-            .cloned()
-            .collect::<LinkedList<_>>();
-        for node in &new_nodes {
-            self.nodes_to_children
-                .insert(node.clone(), Default::default());
-            self.nodes_to_parents
-                .insert(node.clone(), Default::default());
+    /// Only graph the refs containing this revision, along with the revision
+    /// itself
+    pub fn with_contains(mut self, revision: Option<String>) -> Self {
+        self.contains = revision;
+        self
+    }
+
+    /// Stop exploring the merge bases this many levels away from the refs,
+    /// collapsing the older ones into a single root node
+    ///
+    /// A merge base is one level further than the deepest of the two nodes
+    /// it is computed from, the refs being at level 0. This is an
+    /// approximation: the merge bases left out may be the parents of nodes
+    /// other than the ones they were found from, which then get no edge from
+    /// the root.
+    pub fn with_depth(mut self, depth: Option<usize>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Keep the edges from a node to the descendants also reachable through
+    /// other nodes when `false`, to see every computed merge base relation
+    pub fn with_prune(mut self, prune: bool) -> Self {
+        self.prune = prune;
+        self
+    }
+
+    /// Leave out the merge bases with a single parent and a single child,
+    /// linking the two directly with an edge counting the hops
+    pub fn with_collapse_linear(mut self, collapse_linear: bool) -> Self {
+        self.collapse_linear = collapse_linear;
+        self
+    }
+
+    /// Only graph this many refs, the ones whose tip was committed to last
+    ///
+    /// This is an approximation on large repositories: a branch left out
+    /// may still be active, with old commits rebased or cherry-picked.
+    pub fn with_max_branches(mut self, max_branches: Option<usize>) -> Self {
+        self.max_branches = max_branches;
+        self
+    }
+
+    /// Mark the nodes whose refs haven't been committed to for this long
+    pub fn with_stale_after(mut self, stale_after: Option<Duration>) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    /// Show the first line of the commit message of every node
+    pub fn with_show_subject(mut self, show_subject: bool) -> Self {
+        self.show_subject = show_subject;
+        self
+    }
+
+    /// Truncate the subjects to this many characters
+    pub fn with_subject_width(mut self, subject_width: usize) -> Self {
+        self.subject_width = subject_width;
+        self
+    }
+
+    /// Show the author of every node
+    pub fn with_show_author(mut self, show_author: bool) -> Self {
+        self.show_author = show_author;
+        self
+    }
+
+    /// Show the email of the author of every node instead of their name,
+    /// even without `with_show_author()`
+    pub fn with_show_email(mut self, show_email: bool) -> Self {
+        self.show_email = show_email;
+        self
+    }
+
+    /// Show the committer date of every node
+    pub fn with_show_date(mut self, show_date: bool) -> Self {
+        self.show_date = show_date;
+        self
+    }
+
+    /// Format of the dates shown
+    pub fn with_date_format(mut self, date_format: DateFormat) -> Self {
+        self.date_format = date_format;
+        self
+    }
+
+    /// Show the dates in UTC rather than the local time zone
+    pub fn with_utc(mut self, utc: bool) -> Self {
+        self.time_zone = if utc {
+            jiff::tz::TimeZone::UTC
+        } else {
+            jiff::tz::TimeZone::system()
+        };
+        self
+    }
+
+    /// Count the commits between the parent and the child of every edge
+    pub fn with_show_distance(mut self, show_distance: bool) -> Self {
+        self.show_distance = show_distance;
+        self
+    }
+
+    /// Only graph the refs merged into this revision
+    pub fn with_merged(mut self, revision: Option<String>) -> Self {
+        self.merged = revision;
+        self
+    }
+
+    /// Only graph the refs not merged into this revision
+    pub fn with_no_merged(mut self, revision: Option<String>) -> Self {
+        self.no_merged = revision;
+        self
+    }
+
+    /// Count the commits every branch is ahead and behind this ref
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Whether to reuse the merge bases computed by previous runs, and save
+    /// the new ones for the next
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Run this git executable instead of the one on the PATH
+    pub fn with_git_binary(mut self, git_binary: Option<PathBuf>) -> Self {
+        if let Some(git_binary) = git_binary {
+            self.git_binary = git_binary;
         }
+        self
+    }
 
-        while let Some(new_node) = new_nodes.pop_front() {
-            let keys = self.nodes_to_children.keys().cloned().collect::<Vec<_>>();
-            for node in keys {
-                let base = self.merge_base(&new_node, &node)?;
+    /// Report the work done while building the graph to `progress`
+    pub fn with_progress(mut self, progress: impl FnMut(&Stats) + 'static) -> Self {
+        self.progress = Some(Progress(Box::new(progress)));
+        self
+    }
 
-                if !self.nodes_to_children.contains_key(&base) {
-                    self.nodes_to_children
-                        .insert(base.clone(), Default::default());
-                    new_nodes.push_back(base.clone());
-                }
+    /// Resolve the revisions, compute the merge bases and counts, and read
+    /// the commits with this backend instead of gix
+    pub fn with_backend(mut self, backend: impl GitBackend + 'static) -> Self {
+        self.backend = OnceCell::from(Box::new(backend) as Box<dyn GitBackend>);
+        self
+    }
 
-                if let Some(children) = self.nodes_to_children.get_mut(&base) {
-                    if base != node {
-                        children.insert(node.clone());
-                    }
-                    if base != new_node {
-                        children.insert(new_node.clone());
-                    }
+    /// Abbreviation length set by `core.abbrev`, which is either a number
+    /// of characters, `no` for full ids, or `auto`
+    fn config_abbrev(&self) -> usize {
+        let Some(value) = self.config.string("core.abbrev") else {
+            return ABBREV;
+        };
+        let value = value.to_string();
+        match value.to_ascii_lowercase().as_str() {
+            "no" | "false" | "off" => usize::MAX,
+            "auto" => ABBREV,
+            len => match len.parse::<usize>() {
+                Ok(len) => len.max(MIN_ABBREV),
+                Err(_) => {
+                    log::warn!("Ignoring invalid core.abbrev {:?}", value);
+                    ABBREV
                 }
+            },
+        }
+    }
 
-                if !self.nodes_to_parents.contains_key(&node) {
-                    self.nodes_to_parents
-                        .insert(node.clone(), Default::default());
-                }
-                if node != base {
-                    if let Some(parents) = self.nodes_to_parents.get_mut(&node) {
-                        parents.insert(base.clone());
-                    }
-                }
+    /// Working directory of the repository, or its git directory when bare
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
 
-                if !self.nodes_to_parents.contains_key(&new_node) {
-                    self.nodes_to_parents
-                        .insert(new_node.clone(), Default::default());
-                }
-                if new_node != base {
-                    if let Some(parents) = self.nodes_to_parents.get_mut(&new_node) {
-                        parents.insert(base.clone());
-                    }
-                }
-            }
+    /// Git directory shared by every worktree of the repository
+    pub fn git_dir(&self) -> &Path {
+        self.git.common_dir()
+    }
+
+    /// Render options defaulting to the git config of the repository
+    pub fn render_options(&self) -> RenderOptions {
+        let mut options = RenderOptions::default();
+        if let Some(dot_binary) = self.config.string("branch-graph.dotBinary") {
+            options.dot_binary = dot_binary.to_string().into();
         }
+        options
+    }
 
-        let Some(oldest) = self
-            .nodes_to_parents
-            .iter()
-            .find(|(_, parents)| parents.is_empty())
-            .map(|(oldest, _)| oldest)
-            .cloned()
-        else {
-            anyhow::bail!("Unable to determine ultimate parent node");
-        };
+    /// Graph these branches, or the branches matching them when they are
+    /// glob patterns, instead of every local branch
+    pub fn add_branches<I, S>(mut self, branches: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let branches = branches
+            .into_iter()
+            .map(|branch| branch.as_ref().to_string())
+            .collect::<Vec<_>>();
 
-        self.prune_children(oldest.clone());
+        // Check every branch before adding any, so that nothing is left half
+        // done when aborting
+        let mut unmatched = Vec::new();
+        let mut kinds = HashMap::new();
+        for branch in &branches {
+            if is_range_or_path(branch) {
+                return Err(Error::NotARevision(branch.clone()));
+            }
+            if is_glob(branch) {
+                continue;
+            }
+            if let Some(kind) = self.classify(branch)? {
+                kinds.insert(branch, kind);
+            } else {
+                let error = self.not_found(branch)?;
+                if self.strict {
+                    return Err(error);
+                }
+                log::warn!("Skipping: {}", error);
+                unmatched.push(branch.clone());
+            }
+        }
 
-        let mut leaves = self
-            .nodes_to_children
-            .iter()
-            .filter_map(|(node, children)| {
-                if children.is_empty() {
-                    Some(node)
-                } else {
-                    None
+        let count = self.branch_names.len();
+        for branch in &branches {
+            if is_glob(branch) {
+                if self.add_matching_branches(branch)? == 0 {
+                    log::warn!("No branch matches {:?}", branch);
+                    unmatched.push(branch.clone());
                 }
-            })
-            .cloned()
-            .collect::<Vec<_>>();
+            } else if let Some((dir, name)) = kinds.get(branch) {
+                self.add_branch(dir, name)?;
+            }
+        }
+        // Unmatched branches are only fatal when they leave nothing to graph,
+        // instead of silently falling back to every branch
+        if !branches.is_empty() && self.branch_names.len() == count {
+            return Err(Error::NoBranchFound {
+                patterns: unmatched,
+                directory: self.directory.clone(),
+            });
+        }
+
+        Ok(self)
+    }
 
-        leaves.sort_by_key(|leaf| self.nodes_to_parents.get(leaf).map(|p| p.len()));
+    /// Refs fully merged into a target, by default the default branch, like
+    /// `git branch --merged`
+    ///
+    /// The branches are the ones that would be graphed, except the target
+    /// itself and its upstream. Tags are never listed.
+    pub fn merged(&mut self, target: Option<&str>) -> Result<Vec<NodeRef>, Error> {
+        let target = match target {
+            Some(target) => target.to_string(),
+            None => self.default_branch()?,
+        };
+        let Some(commit) = self.resolve(&target)? else {
+            return Err(Error::RevisionNotFound(target));
+        };
+        let upstream = self.upstream(&target);
 
-        for leaf in &leaves {
-            self.prune_parents(leaf.clone());
+        if self.branch_names.is_empty() {
+            self.read_branches()?;
+        }
+        if self.cache {
+            self.load_cache();
         }
 
-        println!("digraph {{");
-        let mut nodes_to_id = HashMap::<Commit, usize>::new();
-        for node in self.nodes_to_children.keys() {
-            let id = nodes_to_id.len();
-            nodes_to_id.insert(node.clone(), id);
-            println!("\t{} [label=\"{}\"]", id, self.name(node));
+        // A tip is merged when it is its own merge base with the target
+        let tips = self.id_to_branches.keys().cloned().collect::<Vec<_>>();
+        self.fill_merge_bases(
+            tips.iter()
+                .map(|tip| merge_base_key(tip, &commit))
+                .collect(),
+        )?;
+        let mut merged = Vec::new();
+        for tip in tips {
+            if self.merge_base(&tip, &commit)? == [tip.clone()] {
+                merged.extend(
+                    self.node(&tip)
+                        .refs
+                        .into_iter()
+                        .filter(|r| matches!(r.kind, RefKind::Branch | RefKind::Remote))
+                        .filter(|r| r.name != target && Some(&r.name) != upstream.as_ref()),
+                );
+            }
         }
-        for (node, children) in self.nodes_to_children.iter() {
-            for child in children {
-                println!("\t{} -> {}", nodes_to_id[node], nodes_to_id[child]);
+        merged.sort_by(|lhs, rhs| (lhs.kind, &lhs.name).cmp(&(rhs.kind, &rhs.name)));
+
+        if self.cache {
+            if let Err(e) = self.save_cache() {
+                log::warn!("Unable to save the merge base cache: {}", e);
             }
         }
-        println!("}}");
 
-        Ok(())
+        Ok(merged)
     }
 
-    fn prune_children(&mut self, parent: Commit) {
-        let mut children = self.nodes_to_children.get(&parent).unwrap().clone();
-        let all_children = children.clone();
-        children.retain(|node| {
-            for child in &all_children {
-                if child == node {
-                    continue;
-                }
-                if self.nodes_to_children.get(child).unwrap().contains(node) {
-                    return false;
-                }
+    /// Local branch the other ones are merged into: the one origin/HEAD
+    /// points to, init.defaultBranch, main or master, whichever exists first
+    fn default_branch(&self) -> Result<String> {
+        let origin_head = self
+            .git
+            .try_find_reference("refs/remotes/origin/HEAD")
+            .map_err(Error::git)?
+            .and_then(|head| {
+                let name = head.target().try_name()?.shorten().to_string();
+                Some(name.split_once('/')?.1.to_string())
+            });
+        let configured = self
+            .config
+            .string("init.defaultBranch")
+            .map(|branch| branch.to_string());
+
+        for branch in origin_head
+            .into_iter()
+            .chain(configured)
+            .chain(["main".into(), "master".into()])
+        {
+            let name = format!("refs/heads/{}", branch);
+            if self
+                .git
+                .try_find_reference(name.as_str())
+                .map_err(Error::git)?
+                .is_some()
+            {
+                return Ok(branch);
             }
-            true
-        });
+        }
 
-        self.nodes_to_children.insert(parent, children);
+        Err(Error::NoDefaultBranch)
+    }
+
+    /// File in the git directory where the merge bases are cached
+    fn cache_path(&self) -> PathBuf {
+        self.git.common_dir().join("branch-graph-cache.json")
+    }
 
-        for child in all_children {
-            self.prune_children(child);
+    /// Delete the merge base cache, returning whether there was one
+    pub fn clear_cache(&self) -> Result<bool, Error> {
+        match std::fs::remove_file(self.cache_path()) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
         }
     }
 
-    fn prune_parents(&mut self, child: Commit) {
-        let mut parents = self.nodes_to_parents.get(&child).unwrap().clone();
-        let all_parents = parents.clone();
+    /// Read the merge bases computed by previous runs, starting afresh when
+    /// the cache is missing or unreadable
+    fn load_cache(&mut self) {
+        let path = self.cache_path();
+        let cache = match std::fs::read(&path) {
+            Ok(content) => match serde_json::from_slice::<MergeBaseCache>(&content) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    log::debug!("Ignoring invalid cache {:?}: {}", path, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                log::debug!("No cache read from {:?}: {}", path, e);
+                return;
+            }
+        };
 
-        parents.retain(|node| {
-            for parent in &all_parents {
-                if parent == node {
-                    continue;
+        for entry in cache.merge_bases {
+            match entry.parse() {
+                Ok((key, bases)) => {
+                    self.cached.insert(key.clone());
+                    self.merge_bases.insert(key, bases);
                 }
-                if self.nodes_to_parents.get(parent).unwrap().contains(node) {
-                    return false;
+                Err(e) => log::debug!("Ignoring cached merge base: {}", e),
+            }
+        }
+        for entry in cache.distances {
+            match entry.parse() {
+                Ok((key, count)) => {
+                    self.distances.insert(key, count);
                 }
+                Err(e) => log::debug!("Ignoring cached distance: {}", e),
             }
-            true
-        });
-
-        self.nodes_to_parents.insert(child, parents);
+        }
+    }
 
-        for parent in all_parents {
-            self.prune_parents(parent);
+    /// Write back the merge bases when new ones were computed, dropping the
+    /// entries of commits that no longer exist
+    fn save_cache(&self) -> std::io::Result<()> {
+        if !self.cache_dirty {
+            return Ok(());
         }
+
+        let exists = |commit: &Commit| commit.id().is_ok_and(|id| self.git.has_object(id));
+        let mut merge_bases = self
+            .merge_bases
+            .iter()
+            .filter(|((lhs, rhs), bases)| exists(lhs) && exists(rhs) && bases.iter().all(exists))
+            .map(|((lhs, rhs), bases)| CachedMergeBase {
+                lhs: lhs.0.clone(),
+                rhs: rhs.0.clone(),
+                bases: bases.iter().map(|base| base.0.clone()).collect(),
+            })
+            .collect::<Vec<_>>();
+        merge_bases.sort_by(|a, b| (&a.lhs, &a.rhs).cmp(&(&b.lhs, &b.rhs)));
+        let mut distances = self
+            .distances
+            .iter()
+            .filter(|((parent, child), _)| exists(parent) && exists(child))
+            .map(|((parent, child), count)| CachedDistance {
+                parent: parent.0.clone(),
+                child: child.0.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        distances.sort_by(|a, b| (&a.parent, &a.child).cmp(&(&b.parent, &b.child)));
+
+        // Write to a temporary file first so concurrent runs never read a
+        // partial cache
+        let path = self.cache_path();
+        let tmp = path.with_extension("json.tmp");
+        let cache = MergeBaseCache {
+            merge_bases,
+            distances,
+        };
+        std::fs::write(&tmp, serde_json::to_vec(&cache)?)?;
+        std::fs::rename(&tmp, &path)?;
+
+        Ok(())
     }
 
-    fn merge_base(&mut self, lhs: &Commit, rhs: &Commit) -> Result<Commit> {
-        let (lhs, rhs) = if rhs > lhs { (rhs, lhs) } else { (lhs, rhs) };
+    /// Compute the graph of merge bases between the branches
+    pub fn build(&mut self) -> Result<BranchGraph, Error> {
+        if self.cache {
+            self.load_cache();
+        }
 
-        if let Some(commit) = self.merge_bases.get(&(lhs.clone(), rhs.clone())) {
-            Ok(commit.clone())
-        } else {
-            let value = cmd!(
-                "git",
-                "-C",
-                self.directory.as_os_str(),
-                "merge-base",
-                lhs.0.as_str(),
-                rhs.0.as_str(),
-            )
-            .read()?;
-            let commit = Commit(value);
-            self.merge_bases
-                .insert((lhs.clone(), rhs.clone()), commit.clone());
+        self.build_nodes()?;
+        if self.show_distance {
+            self.fill_distances()?;
+        }
 
-            Ok(commit)
+        if self.cache {
+            if let Err(e) = self.save_cache() {
+                log::warn!("Unable to save the merge base cache: {}", e);
+            }
         }
+
+        Ok(self.graph())
     }
 
-    fn name(&self, commit: &Commit) -> ColoredString {
-        let hash = commit.0.as_str()[0..9].red();
-        if let Some(names) = self.id_to_branches.get(commit) {
-            format!(
-                "{} {}",
-                hash,
-                names
-                    .iter()
-                    .map(|name| format!("{}", name.as_str().green()))
-                    .collect::<Vec<_>>()
-                    .join(", "),
-            )
-            .into()
-        } else {
-            hash
+    /// Nodes and edges as computed by `build_nodes()`
+    fn graph(&self) -> BranchGraph {
+        let indexes = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node, index))
+            .collect::<HashMap<_, _>>();
+
+        BranchGraph {
+            nodes: self.order.iter().map(|node| self.node(node)).collect(),
+            edges: self
+                .order
+                .iter()
+                .enumerate()
+                .flat_map(|(parent, node)| {
+                    self.ordered_children(node)
+                        .into_iter()
+                        .map(move |child| (parent, child))
+                })
+                .map(|(parent, child)| Edge {
+                    parent,
+                    child: indexes[child],
+                    // The cache may know distances that weren't asked for
+                    distance: self
+                        .distances
+                        .get(&(self.order[parent].clone(), child.clone()))
+                        .filter(|_| self.show_distance)
+                        .copied(),
+                    collapsed: self
+                        .collapsed
+                        .get(&(self.order[parent].clone(), child.clone()))
+                        .copied()
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            tracking: self.tracking(&indexes),
         }
     }
 
-    fn read_branches(&mut self) -> Result<()> {
-        let branches = self
-            .config
-            .sections()
-            .filter_map(|section| {
-                if section.header().name() == "branch" {
-                    if let Some(branch) = section.header().subsection_name() {
-                        Some(branch.to_string())
-                    } else {
-                        None
-                    }
+    /// Links from the local branches to their upstream, when both are in the
+    /// graph on different nodes
+    fn tracking(&self, indexes: &HashMap<&Commit, usize>) -> Vec<Tracking> {
+        let remotes = self
+            .id_to_branches
+            .iter()
+            .flat_map(|(commit, refs)| {
+                refs.iter()
+                    .filter(|r| r.kind == RefKind::Remote)
+                    .map(move |r| (r.name.as_str(), indexes[commit]))
+            })
+            .collect::<HashMap<_, _>>();
+
+        self.order
+            .iter()
+            .flat_map(|commit| {
+                self.id_to_branches
+                    .get(commit)
+                    .into_iter()
+                    .flatten()
+                    .filter(|r| r.kind == RefKind::Branch)
+                    .map(move |r| (&r.name, indexes[commit]))
+            })
+            .filter_map(|(name, branch)| {
+                let upstream = *remotes.get(self.upstream(name)?.as_str())?;
+                (branch != upstream).then_some(Tracking { branch, upstream })
+            })
+            .collect()
+    }
+
+    /// Hash and refs shown for a node
+    fn node(&self, commit: &Commit) -> Node {
+        let info = self.backend().commit_info(commit).ok().flatten();
+        let time = info.as_ref().map(|info| info.time);
+        let author = info
+            .as_ref()
+            .filter(|_| self.show_author || self.show_email)
+            .map(|info| {
+                if self.show_email {
+                    info.author_email.clone()
                 } else {
-                    None
+                    info.author_name.clone()
                 }
+            });
+        let date = time
+            .filter(|_| self.show_date)
+            .map(|time| self.date_format.format(self.local_time(time)));
+        let subject = info
+            .filter(|_| self.show_subject)
+            .map(|info| truncate(&info.subject, self.subject_width));
+        let refs = self
+            .id_to_branches
+            .get(commit)
+            .into_iter()
+            .flatten()
+            .map(|r| NodeRef {
+                name: r.name.clone(),
+                kind: r.kind,
+                remote: (r.kind == RefKind::Remote).then(|| self.remote_of(&r.name)),
+                current: self.is_current(&r.name, r.kind),
+                upstream_name: match r.kind {
+                    RefKind::Branch => self.upstream(&r.name),
+                    _ => None,
+                },
+                upstream: match r.kind {
+                    RefKind::Branch => self.upstream_statuses.get(&r.name).copied(),
+                    _ => None,
+                },
+                target: self.target_statuses.get(r).cloned(),
             })
             .collect::<Vec<_>>();
 
-        for branch in branches {
-            self.add_branch("heads", branch)?;
+        Node {
+            id: commit.clone(),
+            short_id: commit.short(self.abbrev).to_string(),
+            head: self.head_id.as_ref() == Some(commit),
+            // Merge bases are always older than the refs, only the refs can go
+            // stale
+            stale: !refs.is_empty() && time.is_some_and(|time| self.is_stale(time)),
+            time,
+            subject,
+            author,
+            date,
+            refs,
         }
+    }
 
-        Ok(())
+    /// Remote of a remote-tracking branch: the longest remote name prefixing
+    /// it, as remote names may contain slashes
+    fn remote_of(&self, name: &str) -> String {
+        self.git
+            .remote_names()
+            .into_iter()
+            .map(|remote| remote.to_string())
+            .filter(|remote| {
+                name.strip_prefix(remote.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(String::len)
+            .unwrap_or_else(|| name.split('/').next().unwrap_or(name).to_string())
     }
 
-    fn add_branch<T: ToString>(&mut self, dir: &str, branch: T) -> Result<()> {
-        let branch = branch.to_string();
-        log::debug!("add_branch: {:?}", &branch);
+    /// Same time with the offset of the time zone the dates are shown in
+    fn local_time(&self, time: gix::date::Time) -> gix::date::Time {
+        let offset = jiff::Timestamp::from_second(time.seconds)
+            .map_or(jiff::tz::Offset::UTC, |timestamp| {
+                self.time_zone.to_offset(timestamp).0
+            });
+        gix::date::Time::new(time.seconds, offset.seconds())
+    }
 
-        let id = cmd!(
-            "git",
-            "-C",
-            self.directory.as_os_str(),
-            "rev-list",
-            "--max-count=1",
-            branch.as_str(),
-        )
-        .read()?;
+    /// Whether a commit is older than `stale_after`
+    fn is_stale(&self, time: gix::date::Time) -> bool {
+        let Some(stale_after) = self.stale_after else {
+            return false;
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        time.seconds < now.saturating_sub(stale_after).as_secs() as i64
+    }
 
-        self.branch_names.push(branch.clone());
-        self.id_to_branches
-            .entry(id.clone().into())
-            .or_default()
-            .insert(branch.clone());
+    /// Compute the graph of merge bases between the branches, populating
+    /// `nodes_to_children` and `nodes_to_parents`
+    fn build_nodes(&mut self) -> Result<()> {
+        let start = std::time::Instant::now();
 
-        if dir != "heads" || !self.remote {
-            return Ok(());
+        if self.branch_names.is_empty() {
+            self.read_branches()?;
         }
-
-        if let Ok(section) = self.config.section("branch", Some(branch.as_str().into())) {
-            if let Some(remote) = section.body().value("remote") {
-                if let Some(merge) = section.body().value("merge") {
-                    let merge = format!("{}", merge);
-                    if let Some(merge) = merge.strip_prefix("refs/heads/") {
-                        self.add_branch("remotes", format!("{}/{}", remote, merge))?;
-                    }
-                }
-            }
+        if let Some(pattern) = self.tags.clone() {
+            self.read_tags(&pattern)?;
+        }
+        if self.head {
+            self.add_detached_head()?;
+        }
+        if let Some(revision) = self.merged.clone() {
+            self.keep_merged(&revision, true)?;
+        }
+        if let Some(revision) = self.no_merged.clone() {
+            self.keep_merged(&revision, false)?;
+        }
+        if let Some(revision) = self.contains.clone() {
+            self.keep_containing(&revision)?;
+        }
+        if let Some(max_branches) = self.max_branches {
+            self.keep_recent(max_branches);
+        }
+        if self.upstream_status {
+            self.read_upstream_statuses()?;
+        }
+        if let Some(target) = self.target.clone() {
+            self.read_target_statuses(&target)?;
+        }
+
+        let mut new_nodes = self
+            .id_to_branches
+            .keys()
+            .cloned()
+            .collect::<LinkedList<_>>();
+        for node in &new_nodes {
+            self.nodes_to_children
+                .insert(node.clone(), Default::default());
+            self.nodes_to_parents
+                .insert(node.clone(), Default::default());
+        }
+        let mut levels = new_nodes
+            .iter()
+            .map(|node| (node.clone(), 0))
+            .collect::<HashMap<_, _>>();
+        // Merge bases past `depth`, with the nodes they are a parent of
+        let mut cut = BTreeMap::<Commit, HashSet<Commit>>::new();
+
+        // Every pair of refs is compared at least once, so compute them all
+        // up front in parallel rather than one node at a time
+        let nodes = new_nodes.iter().collect::<Vec<_>>();
+        self.fill_merge_bases(
+            nodes
+                .iter()
+                .enumerate()
+                .flat_map(|(i, lhs)| nodes[i..].iter().map(|rhs| merge_base_key(lhs, rhs)))
+                .collect(),
+        )?;
+
+        while let Some(new_node) = new_nodes.pop_front() {
+            self.stats.nodes += 1;
+            self.report();
+            let keys = self.nodes_to_children.keys().cloned().collect::<Vec<_>>();
+            self.fill_merge_bases(
+                keys.iter()
+                    .map(|node| merge_base_key(&new_node, node))
+                    .collect(),
+            )?;
+
+            for node in keys {
+                let bases = self.merge_base(&new_node, &node)?;
+                if bases.is_empty() {
+                    log::debug!(
+                        "No merge base between {} and {}",
+                        CommitDisplay(&new_node, self),
+                        CommitDisplay(&node, self)
+                    );
+                    continue;
+                }
+
+                let level = levels[&new_node].max(levels[&node]) + 1;
+                for base in bases {
+                    if !self.nodes_to_children.contains_key(&base) {
+                        if self.depth.is_some_and(|depth| level > depth) {
+                            cut.entry(base)
+                                .or_default()
+                                .extend([node.clone(), new_node.clone()]);
+                            continue;
+                        }
+                        self.nodes_to_children
+                            .insert(base.clone(), Default::default());
+                        levels.insert(base.clone(), level);
+                        new_nodes.push_back(base.clone());
+                    }
+
+                    if let Some(children) = self.nodes_to_children.get_mut(&base) {
+                        if base != node {
+                            children.insert(node.clone());
+                        }
+                        if base != new_node {
+                            children.insert(new_node.clone());
+                        }
+                    }
+
+                    if !self.nodes_to_parents.contains_key(&node) {
+                        self.nodes_to_parents
+                            .insert(node.clone(), Default::default());
+                    }
+                    if node != base {
+                        if let Some(parents) = self.nodes_to_parents.get_mut(&node) {
+                            parents.insert(base.clone());
+                        }
+                    }
+
+                    if !self.nodes_to_parents.contains_key(&new_node) {
+                        self.nodes_to_parents
+                            .insert(new_node.clone(), Default::default());
+                    }
+                    if new_node != base {
+                        if let Some(parents) = self.nodes_to_parents.get_mut(&new_node) {
+                            parents.insert(base.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        if !cut.is_empty() {
+            self.collapse(cut)?;
+        }
+
+        // Unrelated histories each have their own root, but a DAG always
+        // has at least one
+        if !self.nodes_to_parents.values().any(HashSet::is_empty) {
+            return Err(Error::NoRoot);
+        }
+
+        if self.prune {
+            self.reduce_edges();
+        }
+        if self.collapse_linear {
+            self.collapse_linear();
+        }
+        self.sort_nodes();
+        self.warn_criss_cross();
+
+        log::debug!(
+            "Built graph of {} nodes with {} merge-base queries ({} cached) in {:?}",
+            self.order.len(),
+            self.stats.merge_bases,
+            self.stats.cached,
+            start.elapsed()
+        );
+
+        Ok(())
+    }
+
+    /// Replace the merge bases left out by `depth` with their own best
+    /// common ancestor, one per unrelated history, as parent of the nodes
+    /// they were a parent of
+    fn collapse(&mut self, cut: BTreeMap<Commit, HashSet<Commit>>) -> Result<()> {
+        let bases = cut.keys().cloned().collect::<Vec<_>>();
+        let mut roots = Vec::<(Commit, HashSet<Commit>)>::new();
+        for (base, children) in cut {
+            // Also found closer to the refs through another pair
+            if self.nodes_to_children.contains_key(&base) {
+                roots.push((base, children));
+                continue;
+            }
+            let mut collapsed = false;
+            for (root, root_children) in &mut roots {
+                if !self.nodes_to_children.contains_key(root) {
+                    if let Some(common) = self.merge_base(root, &base)?.into_iter().next() {
+                        *root = common;
+                        root_children.extend(children.iter().cloned());
+                        collapsed = true;
+                        break;
+                    }
+                }
+            }
+            if !collapsed {
+                roots.push((base, children));
+            }
+        }
+
+        for (root, children) in roots {
+            log::debug!(
+                "Collapsing the merge bases past the depth into {}",
+                CommitDisplay(&root, self)
+            );
+            self.nodes_to_parents.entry(root.clone()).or_default();
+            for child in children.into_iter().filter(|child| *child != root) {
+                self.nodes_to_children
+                    .entry(root.clone())
+                    .or_default()
+                    .insert(child.clone());
+                self.nodes_to_parents
+                    .entry(child)
+                    .or_default()
+                    .insert(root.clone());
+            }
+            self.nodes_to_children.entry(root).or_default();
+        }
+
+        let omitted = bases
+            .iter()
+            .filter(|base| !self.nodes_to_children.contains_key(*base))
+            .count();
+        if omitted > 0 {
+            log::warn!(
+                "Omitted {} merge base{} past a depth of {}, and the older ones",
+                omitted,
+                if omitted == 1 { "" } else { "s" },
+                self.depth.unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Only keep the `max_branches` refs whose tip was committed to last
+    fn keep_recent(&mut self, max_branches: usize) {
+        let mut refs = self
+            .id_to_branches
+            .iter()
+            .flat_map(|(tip, refs)| {
+                let time = self
+                    .backend()
+                    .commit_info(tip)
+                    .ok()
+                    .flatten()
+                    .map_or(i64::MIN, |info| info.time.seconds);
+                refs.iter().map(move |r| (time, tip.clone(), r.clone()))
+            })
+            .collect::<Vec<_>>();
+        if refs.len() <= max_branches {
+            return;
+        }
+
+        // Most recent first, then by name for a stable choice between refs of
+        // the same commit
+        refs.sort_by(|(lhs_time, _, lhs), (rhs_time, _, rhs)| {
+            rhs_time.cmp(lhs_time).then_with(|| lhs.cmp(rhs))
+        });
+        let omitted = refs.len() - max_branches;
+        for (_, tip, r) in refs.into_iter().skip(max_branches) {
+            log::debug!("Omitting {:?}, committed to before the others", r.name);
+            if let Some(refs) = self.id_to_branches.get_mut(&tip) {
+                refs.remove(&r);
+                if refs.is_empty() {
+                    self.id_to_branches.remove(&tip);
+                }
+            }
+        }
+        log::warn!(
+            "Omitted {} branch{}, only graphing the {} most recently committed to",
+            omitted,
+            if omitted == 1 { "" } else { "es" },
+            max_branches
+        );
+    }
+
+    /// Order the nodes topologically, parents before their children, so
+    /// that every output format lists them the same way on each run
+    fn sort_nodes(&mut self) {
+        let mut in_degrees = self
+            .nodes_to_children
+            .keys()
+            .map(|node| (node, 0))
+            .collect::<HashMap<_, _>>();
+        for child in self.nodes_to_children.values().flatten() {
+            *in_degrees.entry(child).or_default() += 1;
+        }
+
+        let mut ready = in_degrees
+            .iter()
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(node, _)| self.sort_key(node))
+            .collect::<BTreeSet<_>>();
+        let mut order = Vec::with_capacity(in_degrees.len());
+
+        while let Some((_, node)) = ready.pop_first() {
+            for child in &self.nodes_to_children[node] {
+                if let Some(degree) = in_degrees.get_mut(child) {
+                    *degree -= 1;
+                    if *degree == 0 {
+                        ready.insert(self.sort_key(child));
+                    }
+                }
+            }
+            order.push(node.clone());
+        }
+
+        self.order = order;
+    }
+
+    /// Tie-break between nodes of the same rank: by branch name, then by id
+    fn sort_key<'a>(&'a self, commit: &'a Commit) -> (Option<&'a String>, &'a Commit) {
+        (
+            self.id_to_branches
+                .get(commit)
+                .and_then(|refs| refs.first())
+                .map(|r| &r.name),
+            commit,
+        )
+    }
+
+    /// Children of the given node, in a stable order
+    fn ordered_children(&self, node: &Commit) -> Vec<&Commit> {
+        let mut children = self
+            .nodes_to_children
+            .get(node)
+            .map(|children| children.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        children.sort_by_key(|child| self.sort_key(child));
+        children
+    }
+
+    /// Warn about each criss-cross merge once, whether its merge bases were
+    /// just computed or read from the cache
+    fn warn_criss_cross(&self) {
+        for (lhs, rhs, bases) in self.criss_crosses() {
+            log::warn!(
+                "{} and {} have {} merge bases (criss-cross merge)",
+                CommitDisplay(lhs, self),
+                CommitDisplay(rhs, self),
+                bases.len()
+            );
+        }
+    }
+
+    /// Pairs of nodes with several merge bases, with their merge bases
+    /// sorted. Every pair of descendants of a criss-cross merge shares its
+    /// merge bases, so there is a single pair per set of merge bases: the
+    /// one closest to them in `order`, earliest node first.
+    fn criss_crosses(&self) -> Vec<(&Commit, &Commit, Vec<&Commit>)> {
+        let index = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut criss_crosses = BTreeMap::new();
+        for ((lhs, rhs), bases) in &self.merge_bases {
+            if bases.len() < 2 {
+                continue;
+            }
+            let (Some(&l), Some(&r)) = (index.get(lhs), index.get(rhs)) else {
+                continue;
+            };
+            let mut bases = bases.iter().collect::<Vec<_>>();
+            bases.sort();
+            let (first, second) = if l < r { (lhs, rhs) } else { (rhs, lhs) };
+            let pair = (l.max(r), l.min(r), first, second);
+            criss_crosses
+                .entry(bases)
+                .and_modify(|closest| *closest = pair.min(*closest))
+                .or_insert(pair);
+        }
+
+        criss_crosses
+            .into_iter()
+            .map(|(bases, (_, _, lhs, rhs))| (lhs, rhs, bases))
+            .collect()
+    }
+
+    /// Contract the merge bases without refs that have a single parent and
+    /// a single child into an edge from the parent to the child, except for
+    /// the checked out one
+    fn collapse_linear(&mut self) {
+        let mut linear = self
+            .nodes_to_children
+            .iter()
+            .filter(|(node, children)| {
+                children.len() == 1
+                    && self
+                        .nodes_to_parents
+                        .get(*node)
+                        .is_some_and(|parents| parents.len() == 1)
+                    && !self.id_to_branches.contains_key(*node)
+                    && self.head_id.as_ref() != Some(*node)
+            })
+            .map(|(node, _)| node.clone())
+            .collect::<Vec<_>>();
+        linear.sort();
+
+        for node in linear {
+            let children = self.nodes_to_children.remove(&node).unwrap_or_default();
+            let parents = self.nodes_to_parents.remove(&node).unwrap_or_default();
+            let (Some(child), Some(parent)) =
+                (children.into_iter().next(), parents.into_iter().next())
+            else {
+                continue;
+            };
+            let collapsed = self
+                .collapsed
+                .remove(&(parent.clone(), node.clone()))
+                .unwrap_or_default()
+                + self
+                    .collapsed
+                    .remove(&(node.clone(), child.clone()))
+                    .unwrap_or_default()
+                + 1;
+            log::debug!("Collapsing {}", CommitDisplay(&node, self));
+
+            if let Some(children) = self.nodes_to_children.get_mut(&parent) {
+                children.remove(&node);
+                children.insert(child.clone());
+            }
+            if let Some(parents) = self.nodes_to_parents.get_mut(&child) {
+                parents.remove(&node);
+                parents.insert(parent.clone());
+            }
+            // Without pruning, the parent may already be linked to the child
+            let count = self.collapsed.entry((parent, child)).or_default();
+            *count = (*count).max(collapsed);
+        }
+    }
+
+    /// Transitive reduction of the graph: drop every edge from a node to a
+    /// descendant that is also reachable through another path, however long,
+    /// then rebuild `nodes_to_parents` to match
+    fn reduce_edges(&mut self) {
+        self.sort_nodes();
+        let index = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node, i))
+            .collect::<HashMap<_, _>>();
+
+        // Bitsets of the nodes reachable from each node, by index in `order`.
+        // Children come after their parents in `order`, so walking it
+        // backwards completes the descendants of a node before the node
+        // itself.
+        let words = self.order.len().div_ceil(64);
+        let mut reachable = vec![vec![0u64; words]; self.order.len()];
+        let mut reduced = Vec::with_capacity(self.order.len());
+        for (i, node) in self.order.iter().enumerate().rev() {
+            let mut children = self.nodes_to_children[node]
+                .iter()
+                .map(|child| index[child])
+                .collect::<Vec<_>>();
+            // A child reachable through a sibling comes after that sibling
+            children.sort_unstable();
+
+            let mut kept = HashSet::new();
+            for child in children {
+                if reachable[i][child / 64] & (1 << (child % 64)) != 0 {
+                    continue;
+                }
+                kept.insert(self.order[child].clone());
+
+                let (ancestors, descendants) = reachable.split_at_mut(child);
+                let (node_reach, child_reach) = (&mut ancestors[i], &descendants[0]);
+                for (word, child_word) in node_reach.iter_mut().zip(child_reach) {
+                    *word |= child_word;
+                }
+                node_reach[child / 64] |= 1 << (child % 64);
+            }
+            reduced.push((node.clone(), kept));
+        }
+
+        for parents in self.nodes_to_parents.values_mut() {
+            parents.clear();
+        }
+        for (node, children) in reduced {
+            for child in &children {
+                self.nodes_to_parents
+                    .entry(child.clone())
+                    .or_default()
+                    .insert(node.clone());
+            }
+            self.nodes_to_children.insert(node, children);
+        }
+    }
+
+    /// Best common ancestors of two commits, empty when their histories are
+    /// unrelated
+    fn merge_base(&mut self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>> {
+        let key = merge_base_key(lhs, rhs);
+        self.fill_merge_bases(vec![key.clone()])?;
+
+        Ok(self.merge_bases[&key].clone())
+    }
+
+    /// Compute in bulk the merge bases of every pair missing from the
+    /// `merge_bases` cache
+    fn fill_merge_bases(&mut self, pairs: Vec<(Commit, Commit)>) -> Result<()> {
+        let cached = pairs.iter().filter(|key| self.cached.remove(key)).count();
+        let pairs = pairs
+            .into_iter()
+            .filter(|key| !self.merge_bases.contains_key(key))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if cached == 0 && pairs.is_empty() {
+            return Ok(());
+        }
+
+        let results = self.backend().merge_bases(&pairs)?;
+        self.stats.merge_bases += cached + pairs.len();
+        self.stats.cached += cached;
+        for ((lhs, rhs), commits) in pairs.into_iter().zip(results) {
+            self.merge_bases.insert((lhs, rhs), commits);
+            self.cache_dirty = true;
+        }
+        self.report();
+
+        Ok(())
+    }
+
+    fn report(&mut self) {
+        if let Some(Progress(progress)) = &mut self.progress {
+            progress(&self.stats);
+        }
+    }
+
+    /// Count the commits of every edge missing from the `distances` cache
+    fn fill_distances(&mut self) -> Result<()> {
+        let edges = self
+            .order
+            .iter()
+            .flat_map(|parent| {
+                self.ordered_children(parent)
+                    .into_iter()
+                    .map(move |child| (parent.clone(), child.clone()))
+            })
+            .filter(|key| !self.distances.contains_key(key))
+            .collect::<Vec<_>>();
+        if edges.is_empty() {
+            return Ok(());
+        }
+
+        let counts = self.backend().counts(&edges)?;
+        for (key, count) in edges.into_iter().zip(counts) {
+            self.distances.insert(key, count);
+            self.cache_dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Compare every local branch in the graph to its upstream
+    fn read_upstream_statuses(&mut self) -> Result<()> {
+        let branches = self
+            .id_to_branches
+            .iter()
+            .flat_map(|(tip, refs)| refs.iter().map(move |r| (tip.clone(), r)))
+            .filter(|(_, r)| r.kind == RefKind::Branch)
+            .map(|(tip, r)| (tip, r.name.clone()))
+            .collect::<Vec<_>>();
+
+        for (tip, branch) in branches {
+            let Some(upstream) = self.upstream(&branch) else {
+                continue;
+            };
+
+            let status = match self.resolve(&format!("refs/remotes/{}", upstream))? {
+                None => UpstreamStatus::Gone,
+                Some(upstream) => {
+                    let (ahead, behind) = self.backend().ahead_behind(&tip, &upstream)?;
+                    UpstreamStatus::Tracking { ahead, behind }
+                }
+            };
+            self.upstream_statuses.insert(branch, status);
+        }
+
+        Ok(())
+    }
+
+    /// Compare every branch in the graph to the target
+    fn read_target_statuses(&mut self, target: &str) -> Result<()> {
+        let Some(target_id) = self.resolve(target)? else {
+            return Err(Error::RevisionNotFound(target.to_string()));
+        };
+
+        let refs = self
+            .id_to_branches
+            .iter()
+            .flat_map(|(tip, refs)| refs.iter().map(move |r| (tip.clone(), r.clone())))
+            .filter(|(_, r)| {
+                matches!(r.kind, RefKind::Branch | RefKind::Remote) && r.name != target
+            })
+            .collect::<Vec<_>>();
+        for (tip, r) in refs {
+            let (ahead, behind) = self.backend().ahead_behind(&tip, &target_id)?;
+            let status = TargetStatus {
+                target: target.to_string(),
+                ahead,
+                behind,
+            };
+            self.target_statuses.insert(r, status);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a ref is the one checked out: the current branch or the
+    /// detached HEAD
+    fn is_current(&self, name: &str, kind: RefKind) -> bool {
+        match kind {
+            RefKind::Head => true,
+            RefKind::Branch => self.current_branch.as_deref() == Some(name),
+            _ => false,
+        }
+    }
+
+    /// Add the detached HEAD as its own node
+    fn add_detached_head(&mut self) -> Result<()> {
+        if self.current_branch.is_some() {
+            return Ok(());
+        }
+        let Some(id) = self.head_id.clone() else {
+            // Unborn branch
+            return Ok(());
+        };
+
+        let name = String::from("HEAD (detached)");
+        self.branch_names.push(name.clone());
+        self.id_to_branches.entry(id).or_default().insert(Ref {
+            name,
+            kind: RefKind::Head,
+        });
+
+        Ok(())
+    }
+
+    /// Drop the refs that don't contain a revision, and add the revision as
+    /// its own node
+    fn keep_containing(&mut self, revision: &str) -> Result<()> {
+        let Some(commit) = self.resolve(revision)? else {
+            return Err(Error::RevisionNotFound(revision.to_string()));
+        };
+
+        // The revision is an ancestor of a tip when it is their merge base
+        let tips = self.id_to_branches.keys().cloned().collect::<Vec<_>>();
+        self.fill_merge_bases(
+            tips.iter()
+                .map(|tip| merge_base_key(&commit, tip))
+                .collect(),
+        )?;
+        for tip in tips {
+            if self.merge_base(&commit, &tip)? != [commit.clone()] {
+                log::debug!("{} doesn't contain {}", CommitDisplay(&tip, self), revision);
+                self.id_to_branches.remove(&tip);
+            }
+        }
+        if self.id_to_branches.is_empty() {
+            return Err(Error::NoBranchContains(revision.to_string()));
+        }
+
+        // Naming a ref pointing to the commit shouldn't change how it is shown
+        let refs = self.id_to_branches.entry(commit).or_default();
+        if !refs.iter().any(|r| r.name == revision) {
+            refs.insert(Ref {
+                name: revision.to_string(),
+                kind: RefKind::Revision,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Keep only the refs merged into a revision, or only the ones that
+    /// aren't, like `git branch --merged` and `--no-merged`
+    fn keep_merged(&mut self, revision: &str, merged: bool) -> Result<()> {
+        let Some(commit) = self.resolve(revision)? else {
+            return Err(Error::RevisionNotFound(revision.to_string()));
+        };
+
+        // A tip is merged when it is its own merge base with the revision
+        let tips = self.id_to_branches.keys().cloned().collect::<Vec<_>>();
+        self.fill_merge_bases(
+            tips.iter()
+                .map(|tip| merge_base_key(tip, &commit))
+                .collect(),
+        )?;
+        for tip in tips {
+            if (self.merge_base(&tip, &commit)? == [tip.clone()]) != merged {
+                log::debug!(
+                    "{} is {}merged into {}",
+                    CommitDisplay(&tip, self),
+                    if merged { "not " } else { "" },
+                    revision
+                );
+                self.id_to_branches.remove(&tip);
+            }
+        }
+        if self.id_to_branches.is_empty() {
+            return Err(if merged {
+                Error::NoBranchMerged(revision.to_string())
+            } else {
+                Error::NoBranchUnmerged(revision.to_string())
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_branches(&mut self) -> Result<()> {
+        if self.all {
+            for (dir, name) in self.all_refs()? {
+                if !self.is_excluded(&name) {
+                    self.add_branch(dir, name)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let branches = if self.configured_only {
+            self.configured_branches()
+        } else {
+            self.local_branches()?
+        };
+
+        for branch in branches {
+            if !self.is_excluded(&branch) {
+                self.add_branch("heads", branch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the local branches, and the remote-tracking branches with
+    /// `--remote`, whose name matches a glob pattern, returning how many
+    /// were found
+    fn add_matching_branches(&mut self, pattern: &str) -> Result<usize> {
+        use gix::glob::wildmatch;
+
+        let matches = self
+            .all_refs()?
+            .into_iter()
+            .filter(|(dir, name)| {
+                (*dir == "heads" || (*dir == "remotes" && self.remote))
+                    && wildmatch(
+                        pattern.into(),
+                        name.as_str().into(),
+                        wildmatch::Mode::empty(),
+                    )
+                    && !self.is_excluded(name)
+            })
+            .collect::<Vec<_>>();
+
+        for (dir, name) in &matches {
+            self.add_branch(dir, name)?;
+        }
+
+        Ok(matches.len())
+    }
+
+    /// Add the tags whose name matches a glob pattern
+    fn read_tags(&mut self, pattern: &str) -> Result<()> {
+        use gix::glob::wildmatch;
+
+        let tags = self
+            .git
+            .references()
+            .map_err(Error::git)?
+            .tags()
+            .map_err(Error::git)?
+            .map(|reference| {
+                reference
+                    .map(|reference| reference.name().shorten().to_string())
+                    .map_err(Error::boxed)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for tag in tags {
+            let matches = wildmatch(
+                pattern.into(),
+                tag.as_str().into(),
+                wildmatch::Mode::empty(),
+            );
+            if matches && !self.is_excluded(&tag) {
+                self.add_branch("tags", tag)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a discovered ref matches one of the `--exclude` patterns.
+    /// Branches given explicitly are never excluded
+    fn is_excluded(&self, name: &str) -> bool {
+        use gix::glob::wildmatch;
+
+        self.exclude.iter().any(|pattern| {
+            wildmatch(
+                pattern.as_str().into(),
+                name.into(),
+                wildmatch::Mode::empty(),
+            )
+        })
+    }
+
+    /// Every loose or packed ref under refs/heads
+    fn local_branches(&self) -> Result<Vec<String>> {
+        self.git
+            .references()
+            .map_err(Error::git)?
+            .local_branches()
+            .map_err(Error::git)?
+            .map(|reference| {
+                reference
+                    .map(|reference| reference.name().shorten().to_string())
+                    .map_err(Error::boxed)
+            })
+            .collect()
+    }
+
+    /// Every local branch, remote-tracking branch and tag, along with the
+    /// subdirectory of refs/ it lives in
+    fn all_refs(&self) -> Result<Vec<(&'static str, String)>> {
+        use gix::reference::Category;
+
+        let mut refs = Vec::new();
+        for reference in self
+            .git
+            .references()
+            .map_err(Error::git)?
+            .all()
+            .map_err(Error::git)?
+        {
+            let reference = reference.map_err(Error::boxed)?;
+            // Skip symbolic refs like refs/remotes/origin/HEAD, their target
+            // is listed anyway
+            if reference.target().try_id().is_none() {
+                continue;
+            }
+            let Some((category, name)) = reference.name().category_and_short_name() else {
+                continue;
+            };
+            let dir = match category {
+                Category::LocalBranch => "heads",
+                Category::RemoteBranch => "remotes",
+                Category::Tag => "tags",
+                _ => continue,
+            };
+            refs.push((dir, name.to_string()));
+        }
+
+        Ok(refs)
+    }
+
+    /// Branches with a `branch.<name>` config section, which usually means
+    /// they have an upstream
+    fn configured_branches(&self) -> Vec<String> {
+        self.config
+            .sections()
+            .filter_map(|section| {
+                if section.header().name() == "branch" {
+                    section
+                        .header()
+                        .subsection_name()
+                        .map(|branch| branch.to_string())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Where a branch given explicitly points, as the subdirectory of refs/
+    /// and the short name to add it with, or `None` if it doesn't exist
+    ///
+    /// A local branch comes first, then a remote-tracking branch and a tag.
+    /// Anything else resolving to a commit, like an id or `main~2`, is a
+    /// `revision`.
+    fn classify(&self, branch: &str) -> Result<Option<(&'static str, String)>> {
+        const DIRS: [&str; 3] = ["heads", "remotes", "tags"];
+
+        // Revisions like `main~2` aren't even valid ref names
+        let exists = |name: &str| -> Result<bool> {
+            if gix::refs::FullName::try_from(name).is_err() {
+                return Ok(false);
+            }
+            Ok(self
+                .git
+                .try_find_reference(name)
+                .map_err(Error::git)?
+                .is_some())
+        };
+        if let Some((dir, name)) = branch
+            .strip_prefix("refs/")
+            .and_then(|name| name.split_once('/'))
+        {
+            if let Some(dir) = DIRS.into_iter().find(|known| *known == dir) {
+                return Ok(exists(branch)?.then(|| (dir, name.to_string())));
+            }
+        }
+        for dir in DIRS {
+            if exists(&format!("refs/{}/{}", dir, branch))? {
+                return Ok(Some((dir, branch.to_string())));
+            }
+        }
+
+        Ok(self
+            .resolve(branch)?
+            .map(|_| ("revision", branch.to_string())))
+    }
+
+    fn add_branch<T: ToString>(&mut self, dir: &str, branch: T) -> Result<()> {
+        let branch = branch.to_string();
+        log::debug!("add_branch: {:?}", &branch);
+
+        // Use the full name of the refs so that they can't be mistaken for
+        // another kind of ref with the same short name
+        let (rev, kind) = match dir {
+            "revision" => (branch.clone(), RefKind::Revision),
+            _ => (format!("refs/{}/{}", dir, branch), RefKind::from_dir(dir)),
+        };
+        let Some(id) = self.resolve(&rev)? else {
+            return Err(self.not_found(&branch)?);
+        };
+
+        self.branch_names.push(branch.clone());
+        self.id_to_branches.entry(id).or_default().insert(Ref {
+            name: branch.clone(),
+            kind,
+        });
+
+        if dir == "heads" && self.remote {
+            if let Some(upstream) = self.upstream(&branch) {
+                let exists = self
+                    .git
+                    .try_find_reference(format!("refs/remotes/{}", upstream).as_str())
+                    .map_err(Error::git)?
+                    .is_some();
+                if !exists {
+                    log::info!("Upstream {:?} of {:?} is gone", upstream, branch);
+                } else if !self.is_excluded(&upstream) {
+                    self.add_branch("remotes", upstream)?;
+                }
+            }
         }
 
         Ok(())
     }
+
+    /// Commit a revision points to, peeling annotated tags, or `None` if it
+    /// doesn't exist
+    fn resolve(&self, rev: &str) -> Result<Option<Commit>> {
+        self.backend().resolve_ref(rev)
+    }
+
+    fn backend(&self) -> &dyn GitBackend {
+        self.backend
+            .get_or_init(|| Box::new(GixBackend::new(self.git.clone(), self.git_binary.clone())))
+            .as_ref()
+    }
+
+    /// Error for a branch that doesn't exist, suggesting the local branches
+    /// with a close name
+    fn not_found(&self, branch: &str) -> Result<Error> {
+        let mut suggestions = self
+            .local_branches()?
+            .into_iter()
+            .map(|name| (edit_distance(branch, &name), name))
+            .filter(|(distance, _)| *distance <= (branch.chars().count() / 3).max(2))
+            .collect::<Vec<_>>();
+        suggestions.sort();
+
+        Ok(Error::BranchNotFound {
+            branch: branch.to_string(),
+            directory: self.directory.clone(),
+            suggestions: suggestions
+                .into_iter()
+                .take(3)
+                .map(|(_, name)| name)
+                .collect(),
+        })
+    }
+
+    /// Remote-tracking branch configured as upstream of a local branch, from
+    /// its `branch.<name>.remote` and `branch.<name>.merge` config
+    fn upstream(&self, branch: &str) -> Option<String> {
+        let section = self.config.section("branch", Some(branch.into())).ok()?;
+        let remote = section.body().value("remote")?;
+        let merge = format!("{}", section.body().value("merge")?);
+        let merge = merge.strip_prefix("refs/heads/")?;
+
+        Some(format!("{}/{}", remote, merge))
+    }
+}
+
+/// Cut a text to a number of characters, ending with an ellipsis when
+/// shortened
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated = text
+        .chars()
+        .take(width.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// Whether a branch argument is a glob pattern rather than a revision
+fn is_glob(branch: &str) -> bool {
+    branch.contains(['*', '?', '['])
+}
+
+/// Whether a revision is a range like `main..topic` or `^main`, or names a
+/// path like `main:README`, rather than a single commit
+fn is_range_or_path(revision: &str) -> bool {
+    // Dates like `main@{2024-01-31 12:00}` have colons, and `:/fix` searches
+    // for a commit message
+    let before_date = revision.split("@{").next().unwrap_or(revision);
+    revision.starts_with('^')
+        || revision.contains("..")
+        || revision.contains("^-")
+        || revision.ends_with("^!")
+        || revision.ends_with("^@")
+        || (before_date.contains(':') && !revision.starts_with(":/"))
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(lhs: &str, rhs: &str) -> usize {
+    let rhs = rhs.chars().collect::<Vec<_>>();
+    let mut previous = (0..=rhs.len()).collect::<Vec<_>>();
+    let mut current = vec![0; rhs.len() + 1];
+
+    for (i, l) in lhs.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, r) in rhs.iter().enumerate() {
+            let substitution = previous[j] + usize::from(l != *r);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[rhs.len()]
+}
+
+/// Key of the `merge_bases` cache, which doesn't depend on the order of the
+/// commits
+fn merge_base_key(lhs: &Commit, rhs: &Commit) -> (Commit, Commit) {
+    if rhs > lhs {
+        (rhs.clone(), lhs.clone())
+    } else {
+        (lhs.clone(), rhs.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeBackend, Fixture};
+
+    type History<'a> = &'a [(&'a str, &'a [&'a str])];
+    type Edges<'a> = &'a [(&'a str, &'a str)];
+
+    /// Edges of the graph of `branches` built from a hand-written history,
+    /// by commit name
+    fn edges(history: History, branches: &[&str], prune: bool) -> Vec<(String, String)> {
+        let fixture = Fixture::new();
+        let graph = fixture
+            .open()
+            .with_prune(prune)
+            .with_backend(FakeBackend::new(history))
+            .add_branches(branches)
+            .unwrap()
+            .build()
+            .unwrap();
+        let name = |index: usize| FakeBackend::name(&graph.nodes[index].id);
+        graph
+            .edges
+            .iter()
+            .map(|edge| (name(edge.parent), name(edge.child)))
+            .collect()
+    }
+
+    #[test]
+    fn build_nodes() {
+        let cases: &[(&str, History, &[&str], Edges)] = &[
+            (
+                "linear",
+                &[("o", &[]), ("a", &["o"]), ("b", &["a"])],
+                &["a", "b"],
+                &[("a", "b")],
+            ),
+            (
+                "fork",
+                &[("o", &[]), ("a", &["o"]), ("b", &["o"])],
+                &["a", "b"],
+                &[("o", "a"), ("o", "b")],
+            ),
+            ("unrelated", &[("a", &[]), ("b", &[])], &["a", "b"], &[]),
+            (
+                "nested forks",
+                &[
+                    ("o", &[]),
+                    ("a", &["o"]),
+                    ("b", &["a"]),
+                    ("c", &["a"]),
+                    ("e", &["o"]),
+                ],
+                &["b", "c", "e"],
+                &[("o", "a"), ("o", "e"), ("a", "b"), ("a", "c")],
+            ),
+            (
+                "diamond",
+                &[
+                    ("o", &[]),
+                    ("a", &["o"]),
+                    ("b", &["a"]),
+                    ("c", &["a"]),
+                    ("d", &["b", "c"]),
+                ],
+                &["b", "c", "d"],
+                &[("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")],
+            ),
+            (
+                "criss-cross",
+                &[
+                    ("o", &[]),
+                    ("a", &["o"]),
+                    ("b", &["o"]),
+                    ("c", &["a", "b"]),
+                    ("d", &["b", "a"]),
+                ],
+                &["c", "d"],
+                &[
+                    ("o", "a"),
+                    ("o", "b"),
+                    ("a", "c"),
+                    ("a", "d"),
+                    ("b", "c"),
+                    ("b", "d"),
+                ],
+            ),
+        ];
+
+        for (name, history, branches, expected) in cases {
+            let expected = expected
+                .iter()
+                .map(|(parent, child)| (parent.to_string(), child.to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(edges(history, branches, true), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn prune_diamond() {
+        let history: History = &[
+            ("o", &[]),
+            ("a", &["o"]),
+            ("b", &["a"]),
+            ("c", &["a"]),
+            ("d", &["b", "c"]),
+        ];
+        let fixture = Fixture::new();
+        // `a` is also a merge base of `d` with `b` and `c`, which already
+        // link `a` to `d`
+        for (prune, expected) in [
+            (
+                true,
+                owned(&[("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")]),
+            ),
+            (
+                false,
+                owned(&[("a", "b"), ("a", "c"), ("a", "d"), ("b", "d"), ("c", "d")]),
+            ),
+        ] {
+            let graph = fixture
+                .open()
+                .with_prune(prune)
+                .with_backend(FakeBackend::new(history))
+                .add_branches(["b", "c", "d"])
+                .unwrap()
+                .build()
+                .unwrap();
+            let name = |index: usize| FakeBackend::name(&graph.nodes[index].id);
+
+            let nodes = (0..graph.nodes.len()).map(name).collect::<Vec<_>>();
+            assert_eq!(nodes, ["a", "b", "c", "d"], "prune: {}", prune);
+            let mut edges = graph
+                .edges
+                .iter()
+                .map(|edge| (name(edge.parent), name(edge.child)))
+                .collect::<Vec<_>>();
+            edges.sort();
+            assert_eq!(edges, expected, "prune: {}", prune);
+        }
+    }
+
+    /// Edges left by `reduce_edges()` out of these edges, sorted
+    fn reduce(edges: &[(String, String)]) -> Vec<(String, String)> {
+        let fixture = Fixture::new();
+        let mut repository = fixture.open();
+        for (parent, child) in edges {
+            let (parent, child) = (FakeBackend::id(parent), FakeBackend::id(child));
+            repository
+                .nodes_to_children
+                .entry(child.clone())
+                .or_default();
+            repository
+                .nodes_to_children
+                .entry(parent.clone())
+                .or_default()
+                .insert(child.clone());
+            repository
+                .nodes_to_parents
+                .entry(parent.clone())
+                .or_default();
+            repository
+                .nodes_to_parents
+                .entry(child)
+                .or_default()
+                .insert(parent);
+        }
+
+        repository.reduce_edges();
+        let mut reduced = repository
+            .nodes_to_children
+            .iter()
+            .flat_map(|(parent, children)| {
+                children
+                    .iter()
+                    .map(|child| (FakeBackend::name(parent), FakeBackend::name(child)))
+            })
+            .collect::<Vec<_>>();
+        reduced.sort();
+        reduced
+    }
+
+    fn owned(edges: Edges) -> Vec<(String, String)> {
+        edges
+            .iter()
+            .map(|(parent, child)| (parent.to_string(), child.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reduce_edges_drops_shortcut() {
+        assert_eq!(
+            reduce(&owned(&[("a", "b"), ("b", "c"), ("a", "c")])),
+            owned(&[("a", "b"), ("b", "c")])
+        );
+    }
+
+    #[test]
+    fn reduce_edges_drops_long_shortcut() {
+        assert_eq!(
+            reduce(&owned(&[("a", "b"), ("b", "c"), ("c", "d"), ("a", "d")])),
+            owned(&[("a", "b"), ("b", "c"), ("c", "d")])
+        );
+    }
+
+    #[test]
+    fn reduce_edges_long_chain() {
+        let name = |i: usize| format!("c{:05}", i);
+        let chain = (0..10_000)
+            .map(|i| (name(i), name(i + 1)))
+            .collect::<Vec<_>>();
+        let mut edges = chain.clone();
+        edges.push((name(0), name(10_000)));
+        assert_eq!(reduce(&edges), chain);
+    }
+
+    #[test]
+    fn criss_cross() {
+        let fixture = Fixture::new();
+        let o = fixture.commit("o");
+        fixture.git(&["branch", "b"]);
+        let a = fixture.commit("a");
+        fixture.git(&["switch", "--quiet", "b"]);
+        let b = fixture.commit("b");
+        fixture.git(&["switch", "--quiet", "--create", "d"]);
+        let d = fixture.merge("main", "d");
+        fixture.git(&["switch", "--quiet", "main"]);
+        let c = fixture.merge("b", "c");
+        let mut tips = Vec::new();
+        for (branch, merge) in [("e", &c), ("f", &d)] {
+            fixture.git(&["switch", "--quiet", "--create", branch, merge.as_str()]);
+            tips.push(fixture.commit(branch));
+        }
+        let (e, f) = (&tips[0], &tips[1]);
+
+        let mut repository = fixture
+            .open()
+            .add_branches(["main", "d", "e", "f"])
+            .unwrap();
+        let graph = repository.build().unwrap();
+
+        // Each pair of tips has the same two merge bases, only the merges
+        // themselves are reported
+        let mut bases = vec![&a, &b];
+        bases.sort();
+        assert_eq!(repository.criss_crosses(), [(&d, &c, bases)]);
+
+        let mut edges = graph
+            .edges
+            .iter()
+            .map(|edge| (&graph.nodes[edge.parent].id, &graph.nodes[edge.child].id))
+            .collect::<Vec<_>>();
+        edges.sort();
+        let mut expected = vec![
+            (&o, &a),
+            (&o, &b),
+            (&a, &c),
+            (&a, &d),
+            (&b, &c),
+            (&b, &d),
+            (&c, e),
+            (&d, f),
+        ];
+        expected.sort();
+        assert_eq!(edges, expected);
+    }
+
+    #[test]
+    fn exclude_and_explicit_branches() {
+        let fixture = Fixture::new();
+        fixture.commit("root");
+        fixture.git(&["branch", "feature/a"]);
+        fixture.git(&["branch", "feature/b"]);
+
+        let cases: &[(&[&str], &[&str], &[&str])] = &[
+            (&["feature/*"], &[], &["main"]),
+            (&["feature/*"], &["feature/a"], &["feature/a"]),
+            (
+                &["feature/*"],
+                &["main", "feature/a"],
+                &["feature/a", "main"],
+            ),
+            (
+                &["main"],
+                &["main", "feature/*"],
+                &["feature/a", "feature/b", "main"],
+            ),
+            (&["feature/b"], &["feature/*"], &["feature/a"]),
+            (&["feature/*"], &["feature/*", "feature/b"], &["feature/b"]),
+        ];
+        for (exclude, branches, expected) in cases {
+            let graph = fixture
+                .open()
+                .with_exclude(exclude.iter().map(|pattern| pattern.to_string()).collect())
+                .add_branches(*branches)
+                .unwrap()
+                .build()
+                .unwrap();
+            let names = graph
+                .nodes
+                .iter()
+                .flat_map(|node| &node.refs)
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, *expected, "{:?} excluding {:?}", branches, exclude);
+        }
+    }
+
+    #[test]
+    fn packed_refs() {
+        let fixture = Fixture::new();
+        fixture.commit("root");
+        fixture.git(&["branch", "feature/a"]);
+        fixture.commit("main");
+        fixture.git(&["pack-refs", "--all"]);
+        assert!(!fixture.path().join(".git/refs/heads/main").exists());
+
+        for branches in [&["main", "feature/a"][..], &[]] {
+            let graph = fixture
+                .open()
+                .add_branches(branches)
+                .unwrap()
+                .build()
+                .unwrap();
+            let refs = graph
+                .nodes
+                .iter()
+                .flat_map(|node| &node.refs)
+                .map(|r| (r.name.as_str(), r.kind))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                refs,
+                [("feature/a", RefKind::Branch), ("main", RefKind::Branch)],
+                "{:?}",
+                branches
+            );
+        }
+    }
+
+    #[test]
+    fn short_commit_id() {
+        let id = "0123456789abcdef0123456789abcdef01234567";
+        let commit = Commit::parse(id).unwrap();
+        assert_eq!(commit.short(7), "0123456");
+        assert_eq!(commit.short(40), id);
+        assert_eq!(commit.short(usize::MAX), id);
+        // Shorter than the abbreviation, as given to `Commit::from()`
+        assert_eq!(Commit::from(String::from("abc")).short(7), "abc");
+    }
+
+    #[test]
+    fn counts_and_commits_from_the_backend() {
+        let fixture = Fixture::new();
+        let graph = fixture
+            .open()
+            .with_backend(FakeBackend::new(&[
+                ("o", &[]),
+                ("a", &["o"]),
+                ("b", &["a"]),
+                ("c", &["o"]),
+            ]))
+            .with_show_distance(true)
+            .with_show_subject(true)
+            .add_branches(["b", "c"])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let name = |index: usize| FakeBackend::name(&graph.nodes[index].id);
+        let mut distances = graph
+            .edges
+            .iter()
+            .map(|edge| (name(edge.parent), name(edge.child), edge.distance))
+            .collect::<Vec<_>>();
+        distances.sort();
+        assert_eq!(
+            distances,
+            [
+                (String::from("o"), String::from("b"), Some(2)),
+                (String::from("o"), String::from("c"), Some(1)),
+            ]
+        );
+
+        for (index, node) in graph.nodes.iter().enumerate() {
+            assert_eq!(node.subject, Some(name(index)));
+        }
+    }
+
+    #[test]
+    fn abbrev_has_a_minimum() {
+        let fixture = Fixture::new();
+        for (abbrev, short_id) in [(0, "6100"), (4, "6100"), (6, "610000")] {
+            let graph = fixture
+                .open()
+                .with_abbrev(Some(abbrev))
+                .with_backend(FakeBackend::new(&[("a", &[])]))
+                .add_branches(["a"])
+                .unwrap()
+                .build()
+                .unwrap();
+            assert_eq!(graph.nodes[0].short_id, short_id, "--abbrev {}", abbrev);
+        }
+    }
 }
@@ -0,0 +1,218 @@
+//! Helpers shared by the unit tests: real repositories built by running git,
+//! and a backend serving a history written by hand
+
+use crate::{Commit, CommitInfo, Error, GitBackend, Node, NodeRef, RefKind, Repository};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Git repository in a temporary directory, removed on drop
+///
+/// Git runs with a fixed identity and date and without the user's config, so
+/// that the same commands give the same commits everywhere.
+pub(crate) struct Fixture {
+    dir: tempfile::TempDir,
+}
+
+impl Fixture {
+    /// Empty repository with `main` checked out
+    pub(crate) fn new() -> Self {
+        let fixture = Fixture {
+            dir: tempfile::tempdir().expect("temporary directory"),
+        };
+        fixture.git(&["init", "--quiet", "--initial-branch=main"]);
+        fixture
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Run git in the repository, panicking when it fails, and return its
+    /// trimmed output
+    pub(crate) fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.path())
+            .args(args)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", "2025-01-01T00:00:00Z")
+            .env("GIT_COMMITTER_NAME", "Committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", "2025-01-01T00:00:00Z")
+            .output()
+            .expect("git runs");
+        assert!(
+            output.status.success(),
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Empty commit on the checked out branch
+    pub(crate) fn commit(&self, message: &str) -> Commit {
+        self.git(&["commit", "--quiet", "--allow-empty", "--message", message]);
+        self.head()
+    }
+
+    /// Merge commit of `branch` into the checked out branch
+    pub(crate) fn merge(&self, branch: &str, message: &str) -> Commit {
+        self.git(&["merge", "--quiet", "--no-ff", "--message", message, branch]);
+        self.head()
+    }
+
+    fn head(&self) -> Commit {
+        Commit::parse(&self.git(&["rev-parse", "HEAD"])).expect("commit id")
+    }
+
+    /// Repository opened without the merge base cache, which would be
+    /// written into the fixture
+    pub(crate) fn open(&self) -> Repository {
+        Repository::open(self.path())
+            .expect("fixture repository")
+            .with_cache(false)
+    }
+}
+
+/// Node of a graph built by hand, with local branches
+pub(crate) fn node(id: Commit, branches: &[&str]) -> Node {
+    Node {
+        short_id: id.short(7).to_string(),
+        id,
+        head: false,
+        refs: branches
+            .iter()
+            .map(|name| NodeRef {
+                name: name.to_string(),
+                kind: RefKind::Branch,
+                remote: None,
+                current: false,
+                upstream_name: None,
+                upstream: None,
+                target: None,
+            })
+            .collect(),
+        time: None,
+        subject: None,
+        author: None,
+        date: None,
+        stale: false,
+    }
+}
+
+/// Backend serving a history of commits named by hand instead of reading a
+/// repository, each commit resolving from its name
+#[derive(Debug, Default)]
+pub(crate) struct FakeBackend {
+    parents: HashMap<Commit, Vec<Commit>>,
+}
+
+impl FakeBackend {
+    /// History given as each commit with its parents
+    pub(crate) fn new(history: &[(&str, &[&str])]) -> Self {
+        FakeBackend {
+            parents: history
+                .iter()
+                .map(|(commit, parents)| {
+                    (
+                        FakeBackend::id(commit),
+                        parents
+                            .iter()
+                            .map(|parent| FakeBackend::id(parent))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Id of a commit named in the history: its name in hexadecimal, padded
+    /// with zeroes
+    pub(crate) fn id(name: &str) -> Commit {
+        let hex = name
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        assert!(hex.len() <= 40, "{:?} is too long for a commit name", name);
+        Commit::parse(&format!("{:0<40}", hex)).expect("hexadecimal id")
+    }
+
+    /// Name of a commit of the history, from its id
+    pub(crate) fn name(commit: &Commit) -> String {
+        let hex = commit.as_str().as_bytes();
+        let bytes = hex
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .take_while(|b| *b != 0)
+            .collect();
+        String::from_utf8(bytes).expect("name of a commit")
+    }
+
+    /// The commit and every commit it descends from
+    fn ancestors(&self, commit: &Commit) -> HashSet<Commit> {
+        let mut ancestors = HashSet::new();
+        let mut pending = vec![commit.clone()];
+        while let Some(commit) = pending.pop() {
+            if let Some(parents) = self.parents.get(&commit) {
+                if ancestors.insert(commit) {
+                    pending.extend(parents.iter().cloned());
+                }
+            }
+        }
+        ancestors
+    }
+}
+
+impl GitBackend for FakeBackend {
+    fn resolve_ref(&self, rev: &str) -> Result<Option<Commit>> {
+        // Longer names can't be in the history
+        if rev.len() > 20 {
+            return Ok(None);
+        }
+        let id = FakeBackend::id(rev);
+        Ok(self.parents.contains_key(&id).then_some(id))
+    }
+
+    /// The common ancestors that aren't an ancestor of another one
+    fn merge_base(&self, lhs: &Commit, rhs: &Commit) -> Result<Vec<Commit>> {
+        let common = &self.ancestors(lhs) & &self.ancestors(rhs);
+        let mut bases = common
+            .iter()
+            .filter(|base| {
+                !common
+                    .iter()
+                    .any(|other| other != *base && self.ancestors(other).contains(*base))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        bases.sort();
+        Ok(bases)
+    }
+
+    fn ahead_behind(&self, lhs: &Commit, rhs: &Commit) -> Result<(usize, usize)> {
+        let (lhs, rhs) = (self.ancestors(lhs), self.ancestors(rhs));
+        Ok((lhs.difference(&rhs).count(), rhs.difference(&lhs).count()))
+    }
+
+    /// Commits with their name as subject, dated by their number of
+    /// ancestors so that children are more recent than their parents
+    fn commit_info(&self, commit: &Commit) -> Result<Option<CommitInfo>> {
+        if !self.parents.contains_key(commit) {
+            return Ok(None);
+        }
+        let depth = self.ancestors(commit).len() as i64;
+        Ok(Some(CommitInfo {
+            time: gix::date::Time::new(depth, 0),
+            author_name: String::from("Author"),
+            author_email: String::from("author@example.com"),
+            subject: FakeBackend::name(commit),
+        }))
+    }
+}
@@ -0,0 +1,306 @@
+//! Interactive view of the graph, for terminals without an image viewer at
+//! hand
+
+use git_branch_graph::{BranchGraph, Node, NodeRef, RefKind, UpstreamStatus};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::Write;
+
+/// Show the graph until the user quits
+pub fn run(graph: &BranchGraph) -> std::io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = View::new(graph).run(&mut terminal);
+    ratatui::try_restore()?;
+    result
+}
+
+struct View<'a> {
+    graph: &'a BranchGraph,
+    /// Parents and children of every node, by index
+    parents: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+    state: ListState,
+    /// Feedback shown in the status line until the next key
+    message: Option<String>,
+}
+
+impl<'a> View<'a> {
+    fn new(graph: &'a BranchGraph) -> Self {
+        let mut parents = vec![Vec::new(); graph.nodes.len()];
+        let mut children = vec![Vec::new(); graph.nodes.len()];
+        for edge in &graph.edges {
+            parents[edge.child].push(edge.parent);
+            children[edge.parent].push(edge.child);
+        }
+
+        View {
+            graph,
+            parents,
+            children,
+            state: ListState::default().with_selected(Some(0)),
+            message: None,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.message = None;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.state.select_previous(),
+                KeyCode::Home | KeyCode::Char('g') => self.state.select_first(),
+                KeyCode::End | KeyCode::Char('G') => self.state.select_last(),
+                KeyCode::Left | KeyCode::Char('h') => {
+                    let parent = self.parents[self.selected()].first().copied();
+                    self.follow(parent, "parent");
+                }
+                KeyCode::Right | KeyCode::Char('l') => {
+                    let child = self.children[self.selected()].first().copied();
+                    self.follow(child, "child");
+                }
+                KeyCode::Char('y') => self.copy()?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Index of the selected node, `select_last()` only being clamped when
+    /// drawing
+    fn selected(&self) -> usize {
+        self.state
+            .selected()
+            .unwrap_or_default()
+            .min(self.graph.nodes.len().saturating_sub(1))
+    }
+
+    fn follow(&mut self, node: Option<usize>, relation: &str) {
+        match node {
+            Some(node) => self.state.select(Some(node)),
+            None => self.message = Some(format!("No {}", relation)),
+        }
+    }
+
+    /// Copy the selected commit id with the OSC 52 escape sequence, which
+    /// reaches the local clipboard even over SSH
+    fn copy(&mut self) -> std::io::Result<()> {
+        let id = self.graph.nodes[self.selected()].id.as_str();
+        let mut stdout = std::io::stdout();
+        write!(stdout, "\x1b]52;c;{}\x07", base64(id.as_bytes()))?;
+        stdout.flush()?;
+        self.message = Some(format!("Copied {}", id));
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list, details] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+
+        let nodes = List::new(self.graph.nodes.iter().map(node_line))
+            .block(Block::bordered().title("Nodes"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(nodes, list, &mut self.state);
+
+        let details_lines = self.details(self.selected());
+        frame.render_widget(
+            Paragraph::new(details_lines)
+                .block(Block::bordered().title("Details"))
+                .wrap(Wrap { trim: false }),
+            details,
+        );
+
+        let help = "↑↓ move  ← parent  → child  y copy id  q quit";
+        frame.render_widget(
+            Line::from(self.message.as_deref().unwrap_or(help)).dark_gray(),
+            status,
+        );
+    }
+
+    fn details(&self, id: usize) -> Vec<Line<'a>> {
+        let node = &self.graph.nodes[id];
+        let related = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| self.graph.nodes[*id].short_id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut lines = vec![Line::from(vec![
+            "Commit: ".bold(),
+            Span::raw(node.id.as_str()).red(),
+        ])];
+        for (name, value) in [
+            ("Subject: ", &node.subject),
+            ("Author: ", &node.author),
+            ("Date: ", &node.date),
+        ] {
+            if let Some(value) = value {
+                lines.push(Line::from(vec![name.bold(), Span::raw(value.clone())]));
+            }
+        }
+        if !node.refs.is_empty() {
+            lines.push(Line::from("Refs:".bold()));
+            for r in &node.refs {
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    ref_span(node, r),
+                    Span::raw(format!(" ({}){}", r.kind.name(), r.status())),
+                ]));
+                if let Some(upstream) = &r.upstream_name {
+                    let status = match r.upstream {
+                        Some(UpstreamStatus::Tracking { ahead, behind }) => {
+                            format!(" (+{}/-{})", ahead, behind)
+                        }
+                        Some(UpstreamStatus::Gone) => String::from(" (gone)"),
+                        None => String::new(),
+                    };
+                    lines.push(Line::from(vec![
+                        "    Upstream: ".bold(),
+                        Span::raw(format!("{}{}", upstream, status)),
+                    ]));
+                }
+            }
+        }
+        lines.push(Line::from(vec![
+            "Parents: ".bold(),
+            Span::raw(related(&self.parents[id])),
+        ]));
+        lines.push(Line::from(vec![
+            "Children: ".bold(),
+            Span::raw(related(&self.children[id])),
+        ]));
+        lines
+    }
+}
+
+/// Hash and refs of a node, colored like in the log messages
+fn node_line(node: &Node) -> Line<'_> {
+    let mut spans = vec![Span::raw(node.short_id.as_str()).fg(if node.stale {
+        Color::DarkGray
+    } else {
+        Color::Red
+    })];
+    for (i, r) in node.refs.iter().enumerate() {
+        spans.push(Span::raw(if i == 0 { " " } else { ", " }));
+        if r.current {
+            spans.push(Span::raw("* "));
+        }
+        spans.push(ref_span(node, r));
+        spans.push(Span::raw(r.status()));
+    }
+    Line::from(spans)
+}
+
+fn ref_span<'a>(node: &Node, r: &'a NodeRef) -> Span<'a> {
+    let color = match r.kind {
+        _ if node.stale => Color::DarkGray,
+        RefKind::Branch => Color::Green,
+        RefKind::Remote => Color::Cyan,
+        RefKind::Tag => Color::Yellow,
+        RefKind::Head => Color::Blue,
+        RefKind::Revision => Color::Magenta,
+    };
+    let span = Span::raw(r.name.as_str()).fg(color);
+    if r.current {
+        span.bold()
+    } else {
+        span
+    }
+}
+
+/// Standard base64 encoding, for the single use of OSC 52
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git_branch_graph::Commit;
+
+    #[test]
+    fn base64_padding() {
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("\u{ff}", "w78="),
+        ] {
+            assert_eq!(base64(bytes.as_bytes()), encoded, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn details_show_upstream() {
+        let id = Commit::parse(&"1".repeat(40)).unwrap();
+        let graph = BranchGraph {
+            nodes: vec![Node {
+                short_id: id.short(7).to_string(),
+                id,
+                head: false,
+                refs: vec![NodeRef {
+                    name: String::from("main"),
+                    kind: RefKind::Branch,
+                    remote: None,
+                    current: true,
+                    upstream_name: Some(String::from("origin/main")),
+                    upstream: Some(UpstreamStatus::Tracking {
+                        ahead: 2,
+                        behind: 1,
+                    }),
+                    target: None,
+                }],
+                time: None,
+                subject: None,
+                author: None,
+                date: None,
+                stale: false,
+            }],
+            edges: Vec::new(),
+            tracking: Vec::new(),
+        };
+        let lines = View::new(&graph)
+            .details(0)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert!(
+            lines.contains(&String::from("    Upstream: origin/main (+2/-1)")),
+            "{:?}",
+            lines
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Tests of the command line, running the binary on repositories built with
+//! git in temporary directories
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Run git in `directory` with a fixed identity and date and without the
+/// user's config, panicking when it fails, and return its trimmed output
+fn git(directory: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "Author")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_AUTHOR_DATE", "2025-01-01T00:00:00Z")
+        .env("GIT_COMMITTER_NAME", "Committer")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .env("GIT_COMMITTER_DATE", "2025-01-01T00:00:00Z")
+        .output()
+        .expect("git runs");
+    assert!(
+        output.status.success(),
+        "git {}: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Repository with `main` and `feature` forked from its first commit
+fn fixture() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().expect("temporary directory");
+    let path = dir.path();
+    git(path, &["init", "--quiet", "--initial-branch=main"]);
+    git(
+        path,
+        &["commit", "--quiet", "--allow-empty", "--message", "root"],
+    );
+    git(path, &["branch", "feature"]);
+    git(
+        path,
+        &["commit", "--quiet", "--allow-empty", "--message", "main"],
+    );
+    git(path, &["switch", "--quiet", "feature"]);
+    git(
+        path,
+        &["commit", "--quiet", "--allow-empty", "--message", "feature"],
+    );
+    git(path, &["switch", "--quiet", "main"]);
+    dir
+}
+
+/// Run git-branch-graph from `directory`, without the merge base cache
+fn run(directory: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-branch-graph"))
+        .current_dir(directory)
+        .arg("--no-cache")
+        .args(args)
+        .output()
+        .expect("git-branch-graph runs")
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn relative_subdirectory() {
+    let fixture = fixture();
+    std::fs::create_dir_all(fixture.path().join("sub/dir")).unwrap();
+
+    let expected = stdout(&run(fixture.path(), &["--format", "tree"]));
+    for directory in ["sub", "sub/dir"] {
+        let output = run(fixture.path(), &["-C", directory, "--format", "tree"]);
+        assert_eq!(stdout(&output), expected, "-C {}", directory);
+    }
+}
+
+#[test]
+fn failed_render_writes_nothing() {
+    let fixture = fixture();
+    // Prints half an image before failing
+    let dot = fixture.path().join("dot");
+    std::fs::write(&dot, "#!/bin/sh\necho '<svg'\nexit 1\n").unwrap();
+    let mut permissions = std::fs::metadata(&dot).unwrap().permissions();
+    permissions.set_mode(0o755);
+    std::fs::set_permissions(&dot, permissions).unwrap();
+    let output_file = fixture.path().join("graph.svg");
+
+    for dot_binary in [dot.to_str().unwrap(), "/nonexistent/dot"] {
+        let output = run(
+            fixture.path(),
+            &["--format", "svg", "--dot-binary", dot_binary],
+        );
+        assert!(!output.status.success(), "--dot-binary {}", dot_binary);
+        assert!(output.stdout.is_empty(), "--dot-binary {}", dot_binary);
+
+        std::fs::write(&output_file, "previous graph").unwrap();
+        let output = run(
+            fixture.path(),
+            &[
+                "--format",
+                "svg",
+                "--dot-binary",
+                dot_binary,
+                "--output",
+                output_file.to_str().unwrap(),
+            ],
+        );
+        assert!(!output.status.success(), "--dot-binary {}", dot_binary);
+        assert!(output.stdout.is_empty(), "--dot-binary {}", dot_binary);
+        assert_eq!(
+            std::fs::read_to_string(&output_file).unwrap(),
+            "previous graph",
+            "--dot-binary {}",
+            dot_binary
+        );
+        assert!(!fixture.path().join("graph.svg.tmp").exists());
+    }
+}
+
+#[test]
+fn failed_graph_writes_nothing() {
+    let fixture = fixture();
+    let git_binary = fixture.path().join("git");
+    std::fs::write(
+        &git_binary,
+        "#!/bin/sh\necho 'fatal: broken' >&2\nexit 128\n",
+    )
+    .unwrap();
+    let mut permissions = std::fs::metadata(&git_binary).unwrap().permissions();
+    permissions.set_mode(0o755);
+    std::fs::set_permissions(&git_binary, permissions).unwrap();
+    let output_file = fixture.path().join("graph.dot");
+
+    for git_binary in [git_binary.to_str().unwrap(), "/nonexistent/git"] {
+        let output = run(
+            fixture.path(),
+            &["--backend", "git", "--git-binary", git_binary],
+        );
+        assert!(!output.status.success(), "--git-binary {}", git_binary);
+        assert!(output.stdout.is_empty(), "--git-binary {}", git_binary);
+
+        std::fs::write(&output_file, "previous graph").unwrap();
+        let output = run(
+            fixture.path(),
+            &[
+                "--backend",
+                "git",
+                "--git-binary",
+                git_binary,
+                "--output",
+                output_file.to_str().unwrap(),
+            ],
+        );
+        assert!(!output.status.success(), "--git-binary {}", git_binary);
+        assert!(output.stdout.is_empty(), "--git-binary {}", git_binary);
+        assert_eq!(
+            std::fs::read_to_string(&output_file).unwrap(),
+            "previous graph",
+            "--git-binary {}",
+            git_binary
+        );
+        assert!(!fixture.path().join("graph.dot.tmp").exists());
+    }
+}
+
+#[test]
+fn bare_repository() {
+    let fixture = fixture();
+    let bare = tempfile::tempdir().unwrap();
+    git(
+        bare.path(),
+        &["init", "--quiet", "--bare", "--initial-branch=main"],
+    );
+    git(
+        fixture.path(),
+        &[
+            "push",
+            "--quiet",
+            bare.path().to_str().unwrap(),
+            "main",
+            "feature",
+        ],
+    );
+
+    let expected = stdout(&run(fixture.path(), &["--format", "tree"]));
+    let output = stdout(&run(bare.path(), &["--format", "tree"]));
+    assert_eq!(output, expected);
+    assert!(output.contains("main") && output.contains("feature"));
+}
+
+#[test]
+fn remote_tracking_branch_node() {
+    let origin = fixture();
+    let clone = tempfile::tempdir().unwrap();
+    git(
+        origin.path(),
+        &["clone", "--quiet", ".", clone.path().to_str().unwrap()],
+    );
+    git(
+        clone.path(),
+        &["commit", "--quiet", "--allow-empty", "--message", "local"],
+    );
+    let local = git(clone.path(), &["rev-parse", "--short=7", "main"]);
+    let remote = git(clone.path(), &["rev-parse", "--short=7", "origin/main"]);
+
+    let without = stdout(&run(clone.path(), &["main"]));
+    assert!(!without.contains("origin/main"), "{}", without);
+
+    let dot = stdout(&run(clone.path(), &["--remote", "main"]));
+    let mut labels = dot
+        .lines()
+        .filter_map(|line| line.split_once("[label=\"")?.1.split_once('"'))
+        .map(|(label, _)| label)
+        .collect::<Vec<_>>();
+    labels.sort();
+    let mut expected = [
+        format!("{} * main", local),
+        format!("{} origin/main", remote),
+    ];
+    expected.sort();
+    assert_eq!(labels, expected, "{}", dot);
+    // The local branch is ahead of its upstream
+    assert!(dot.contains("\t0 -> 1\n"), "{}", dot);
+}
+
+#[test]
+fn missing_binary_hint() {
+    let fixture = fixture();
+    let output = run(
+        fixture.path(),
+        &["--format", "svg", "--dot-binary", "/nonexistent/dot"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("install Graphviz or configure its path"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("hint: pass --dot-binary"), "{}", stderr);
+}